@@ -1,11 +1,19 @@
 pub use sea_orm_migration::prelude::*;
 
 mod m20250223_000001_create_profiles_table;
+mod m20261017_000001_add_profiles_search_vector;
+mod m20261017_000002_add_profiles_deleted_at;
+mod m20261017_000003_add_profiles_deleted_by;
 
 pub struct Migrator;
 
 impl MigratorTrait for Migrator {
     fn migrations() -> Vec<Box<dyn MigrationTrait>> {
-        vec![Box::new(m20250223_000001_create_profiles_table::Migration)]
+        vec![
+            Box::new(m20250223_000001_create_profiles_table::Migration),
+            Box::new(m20261017_000001_add_profiles_search_vector::Migration),
+            Box::new(m20261017_000002_add_profiles_deleted_at::Migration),
+            Box::new(m20261017_000003_add_profiles_deleted_by::Migration),
+        ]
     }
 }
@@ -0,0 +1,67 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        // Display name matches rank above bio matches. The `simple` config
+        // does no stemming, which suits names and multilingual bios.
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Profiles::Table)
+                    .add_column(
+                        ColumnDef::new(Profiles::SearchVector)
+                            .custom(Alias::new("tsvector"))
+                            .not_null()
+                            .extra(
+                                "GENERATED ALWAYS AS (\
+                                 setweight(to_tsvector('simple', coalesce(display_name, '')), 'A') || \
+                                 setweight(to_tsvector('simple', coalesce(bio, '')), 'B')\
+                                 ) STORED",
+                            ),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx_profiles_search_vector")
+                    .table(Profiles::Table)
+                    .col(Profiles::SearchVector)
+                    .index_type(IndexType::FullText)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_index(
+                Index::drop()
+                    .name("idx_profiles_search_vector")
+                    .table(Profiles::Table)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Profiles::Table)
+                    .drop_column(Profiles::SearchVector)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(DeriveIden)]
+enum Profiles {
+    Table,
+    SearchVector,
+}
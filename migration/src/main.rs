@@ -84,10 +84,10 @@ fn next_serial(src_dir: &PathBuf, date: &str) -> std::io::Result<u32> {
         if let Some(rest) = file_name.strip_prefix(&prefix) {
             // rest starts with the serial digits followed by '_'
             let serial_str: &str = rest.split('_').next().unwrap_or("0");
-            if let Ok(n) = serial_str.parse::<u32>() {
-                if n > max_serial {
-                    max_serial = n;
-                }
+            if let Ok(n) = serial_str.parse::<u32>()
+                && n > max_serial
+            {
+                max_serial = n;
             }
         }
     }
@@ -0,0 +1,753 @@
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use axum::http::{HeaderName, Method};
+use clap::Parser;
+use tracing_subscriber::EnvFilter;
+
+use crate::cors::{self, OriginPattern};
+use crate::rate_limit::{self, RateLimit};
+
+mod layers;
+
+pub use layers::ConfigError;
+use layers::{Key, Kind, Layers, Secret};
+
+/// Settings are layered: built-in defaults, then the TOML file, then
+/// `APP_<SECTION>__<KEY>` environment variables, then `--set` flags.
+#[derive(Parser)]
+#[command(version, about)]
+pub struct Args {
+    /// TOML file to read settings from.
+    #[arg(short, long, env = "APP_CONFIG", value_name = "PATH")]
+    pub config: Option<PathBuf>,
+    /// Override one setting, e.g. `--set server.port=8080`. Repeatable.
+    #[arg(long = "set", value_name = "KEY=VALUE")]
+    pub set: Vec<String>,
+    /// Print the effective configuration, with secrets masked, and exit.
+    #[arg(long)]
+    pub print_config: bool,
+}
+
+/// Every setting, with its default and the variable it was read from before
+/// settings were layered. Keys without a default are unset unless a layer
+/// provides them.
+const KEYS: &[Key] = &[
+    Key::new("server.host", Kind::Str)
+        .default_value("0.0.0.0")
+        .legacy_env("SERVER_HOST"),
+    Key::new("server.port", Kind::Int)
+        .default_value("3000")
+        .legacy_env("SERVER_PORT"),
+    Key::new("server.public_base_url", Kind::Str).legacy_env("PUBLIC_BASE_URL"),
+    Key::new("server.problem_json_errors", Kind::Bool)
+        .default_value("false")
+        .legacy_env("PROBLEM_JSON_ERRORS"),
+    Key::new("server.shutdown_delay_secs", Kind::Int)
+        .default_value("0")
+        .legacy_env("SHUTDOWN_DELAY_SECS"),
+    Key::new("server.shutdown_timeout_secs", Kind::Int)
+        .default_value("30")
+        .legacy_env("SHUTDOWN_TIMEOUT_SECS"),
+    Key::new("database.url", Kind::Str)
+        .legacy_env("DATABASE_URL")
+        .secret(Secret::UrlPassword),
+    Key::new("database.replica_url", Kind::Str).secret(Secret::UrlPassword),
+    Key::new("database.read_your_writes_secs", Kind::Int).default_value("5"),
+    Key::new("database.migrations", Kind::Str).default_value("apply"),
+    Key::new("database.max_connections", Kind::Int).default_value("10"),
+    Key::new("database.min_connections", Kind::Int).default_value("0"),
+    Key::new("database.acquire_timeout_secs", Kind::Int).default_value("30"),
+    Key::new("database.idle_timeout_secs", Kind::Int).default_value("600"),
+    Key::new("database.max_lifetime_secs", Kind::Int).default_value("1800"),
+    Key::new("database.statement_timeout_ms", Kind::Int).default_value("30000"),
+    Key::new("database.slow_statement_ms", Kind::Int).default_value("1000"),
+    Key::new("database.log_statements", Kind::Str).default_value("info"),
+    Key::new("auth.supabase_url", Kind::Str).legacy_env("SUPABASE_URL"),
+    Key::new("auth.jwt_secret", Kind::Str)
+        .legacy_env("SUPABASE_JWT_SECRET")
+        .secret(Secret::Yes),
+    Key::new("auth.jwks_ttl_secs", Kind::Int)
+        .default_value("600")
+        .legacy_env("SUPABASE_JWKS_TTL_SECS"),
+    Key::new("cors.allowed_origins", Kind::List)
+        .default_value("")
+        .legacy_env("CORS_ALLOWED_ORIGINS"),
+    Key::new("cors.allowed_methods", Kind::List)
+        .default_value("GET,POST,PUT,PATCH,DELETE")
+        .legacy_env("CORS_ALLOWED_METHODS"),
+    Key::new("cors.allowed_headers", Kind::List)
+        .default_value("authorization,content-type,if-match,if-none-match,x-request-id")
+        .legacy_env("CORS_ALLOWED_HEADERS"),
+    Key::new("cors.max_age_secs", Kind::Int)
+        .default_value("600")
+        .legacy_env("CORS_MAX_AGE_SECS"),
+    Key::new("cors.allow_credentials", Kind::Bool)
+        .default_value("false")
+        .legacy_env("CORS_ALLOW_CREDENTIALS"),
+    Key::new("logging.format", Kind::Str)
+        .default_value("compact")
+        .legacy_env("LOG_FORMAT"),
+    Key::new("logging.filter", Kind::Str)
+        .default_value("info")
+        .legacy_env("RUST_LOG"),
+    Key::new("metrics.addr", Kind::Str).legacy_env("METRICS_ADDR"),
+    Key::new("metrics.token", Kind::Str)
+        .legacy_env("METRICS_TOKEN")
+        .secret(Secret::Yes),
+    Key::new("tracing.otlp_endpoint", Kind::Str).legacy_env("OTEL_EXPORTER_OTLP_ENDPOINT"),
+    Key::new("tracing.service_name", Kind::Str)
+        .default_value(env!("CARGO_PKG_NAME"))
+        .legacy_env("OTEL_SERVICE_NAME"),
+    Key::new("tracing.sampling_ratio", Kind::Float)
+        .default_value("1.0")
+        .legacy_env("OTEL_SAMPLING_RATIO"),
+    Key::new("rate_limit.default", Kind::Str).legacy_env("RATE_LIMIT_DEFAULT"),
+    Key::new("rate_limit.routes", Kind::Map)
+        .default_value(
+            "POST /auth/callback=10/m,PUT /users/me=30/m,PATCH /users/me=30/m,\
+             POST /users/me/avatar=10/m",
+        )
+        .legacy_env("RATE_LIMIT_ROUTES"),
+    Key::new("rate_limit.trust_forwarded_for", Kind::Bool)
+        .default_value("false")
+        .legacy_env("RATE_LIMIT_TRUST_FORWARDED_FOR"),
+    Key::new("profiles.retention_days", Kind::Int)
+        .default_value("30")
+        .legacy_env("PROFILE_RETENTION_DAYS"),
+    Key::new("profiles.purge_interval_secs", Kind::Int)
+        .default_value("3600")
+        .legacy_env("PROFILE_PURGE_INTERVAL_SECS"),
+    Key::new("uploads.dir", Kind::Str)
+        .default_value("uploads")
+        .legacy_env("UPLOAD_DIR"),
+    Key::new("uploads.avatar_max_bytes", Kind::Int)
+        .default_value("5242880")
+        .legacy_env("AVATAR_MAX_BYTES"),
+];
+
+/// Output format of the logs written to stdout.
+#[derive(Clone, Copy, Debug, Default)]
+pub enum LogFormat {
+    /// Multi-line, human-readable; for local development.
+    Pretty,
+    /// One human-readable line per event.
+    #[default]
+    Compact,
+    /// One JSON object per event, with the request span's fields.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "pretty" => Ok(LogFormat::Pretty),
+            "compact" => Ok(LogFormat::Compact),
+            "json" => Ok(LogFormat::Json),
+            other => Err(format!(
+                "unknown log format {other:?} (pretty, compact or json)"
+            )),
+        }
+    }
+}
+
+/// What startup does about migrations that have not been applied yet.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum MigrationMode {
+    /// Apply them, holding an advisory lock so only one instance migrates.
+    #[default]
+    Apply,
+    /// Refuse to start; migrations are run separately (`make migrate`).
+    Verify,
+    /// Start without checking.
+    Skip,
+}
+
+impl FromStr for MigrationMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "apply" => Ok(MigrationMode::Apply),
+            "verify" => Ok(MigrationMode::Verify),
+            "skip" => Ok(MigrationMode::Skip),
+            other => Err(format!(
+                "unknown migration mode {other:?} (apply, verify or skip)"
+            )),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub database: DatabaseConfig,
+    pub auth: AuthConfig,
+    pub cors: CorsConfig,
+    pub logging: LoggingConfig,
+    pub metrics: MetricsConfig,
+    pub tracing: TracingConfig,
+    pub rate_limit: RateLimitConfig,
+    pub profiles: ProfilesConfig,
+    pub uploads: UploadsConfig,
+}
+
+#[derive(Clone)]
+pub struct ServerConfig {
+    pub host: String,
+    pub port: u16,
+    /// Base URL clients use to reach this API; uploaded files are served
+    /// under `{public_base_url}/uploads`.
+    pub public_base_url: String,
+    /// Render errors as RFC 7807 `application/problem+json` instead of the
+    /// `{"error": {...}}` envelope.
+    pub problem_json_errors: bool,
+    /// How long readiness reports `draining` before the listener stops
+    /// accepting connections, so load balancers can take the instance out.
+    pub shutdown_delay_secs: u64,
+    /// How long in-flight requests and background jobs get to finish once
+    /// the listener has closed.
+    pub shutdown_timeout_secs: u64,
+}
+
+#[derive(Clone)]
+pub struct DatabaseConfig {
+    pub url: String,
+    /// Read replica for reads that can be slightly stale. Uses the same
+    /// pool settings as the primary.
+    pub replica_url: Option<String>,
+    /// How long a user's reads stay on the primary after they write.
+    pub read_your_writes_secs: u64,
+    pub migrations: MigrationMode,
+    pub max_connections: u32,
+    /// Connections kept open even when idle.
+    pub min_connections: u32,
+    /// How long a request waits for a free connection before failing.
+    pub acquire_timeout_secs: u64,
+    /// Idle connections above `min_connections` are closed after this long.
+    pub idle_timeout_secs: u64,
+    /// Connections are recycled after this long, so server-side changes
+    /// (failovers, settings) are picked up.
+    pub max_lifetime_secs: u64,
+    /// Postgres `statement_timeout` set on every connection; 0 disables it.
+    pub statement_timeout_ms: u64,
+    /// Statements slower than this are logged as warnings; 0 disables it.
+    pub slow_statement_ms: u64,
+    /// Level at which every executed statement is logged.
+    pub log_statements: log::LevelFilter,
+}
+
+#[derive(Clone)]
+pub struct AuthConfig {
+    pub supabase_url: String,
+    /// Legacy HS256 shared secret. When unset, only asymmetric tokens signed
+    /// with a key from the project's JWKS are accepted.
+    pub jwt_secret: Option<String>,
+    pub jwks_ttl_secs: u64,
+}
+
+impl AuthConfig {
+    /// Location of the JSON Web Key Set Supabase publishes for this project.
+    pub fn jwks_url(&self) -> String {
+        format!("{}/.well-known/jwks.json", self.issuer())
+    }
+
+    /// The `iss` claim Supabase Auth puts in the tokens it issues.
+    pub fn issuer(&self) -> String {
+        format!("{}/auth/v1", self.supabase_url.trim_end_matches('/'))
+    }
+}
+
+#[derive(Clone)]
+pub struct CorsConfig {
+    /// Origins allowed to make cross-origin requests. Empty means none.
+    pub allowed_origins: Vec<OriginPattern>,
+    pub allowed_methods: Vec<Method>,
+    pub allowed_headers: Vec<HeaderName>,
+    /// How long browsers may cache a preflight response.
+    pub max_age_secs: u64,
+    /// Send `Access-Control-Allow-Credentials: true`, letting browsers
+    /// include cookies and HTTP auth. Not allowed with a `*` origin.
+    pub allow_credentials: bool,
+}
+
+#[derive(Clone)]
+pub struct LoggingConfig {
+    pub format: LogFormat,
+    /// `tracing` filter directives, e.g. `info,sqlx=warn`.
+    pub filter: String,
+}
+
+#[derive(Clone)]
+pub struct MetricsConfig {
+    /// Serve `/metrics` on this separate address (e.g. `127.0.0.1:9100`)
+    /// instead of the public listener.
+    pub addr: Option<String>,
+    /// Bearer token required to scrape `/metrics`. Metrics are disabled when
+    /// neither this nor `addr` is set.
+    pub token: Option<String>,
+}
+
+#[derive(Clone)]
+pub struct TracingConfig {
+    /// OTLP/HTTP collector base URL (e.g. `http://localhost:4318`); spans are
+    /// sent to `{endpoint}/v1/traces`. Trace export is disabled when unset.
+    pub otlp_endpoint: Option<String>,
+    pub service_name: String,
+    /// Fraction of new traces to sample, from 0.0 to 1.0. Requests that carry
+    /// a `traceparent` follow the caller's sampling decision instead.
+    pub sampling_ratio: f64,
+}
+
+#[derive(Clone)]
+pub struct RateLimitConfig {
+    /// Limit shared by every route without its own entry in `routes`. None
+    /// leaves those routes unlimited.
+    pub default: Option<RateLimit>,
+    /// Limits keyed by `METHOD /route/template`.
+    pub routes: BTreeMap<String, RateLimit>,
+    /// Key anonymous clients by the last `X-Forwarded-For` entry instead of
+    /// the peer address. Only enable behind a proxy that sets the header.
+    pub trust_forwarded_for: bool,
+}
+
+#[derive(Clone)]
+pub struct ProfilesConfig {
+    /// How long a soft-deleted profile can be restored before it is purged.
+    pub retention_days: i64,
+    pub purge_interval_secs: u64,
+}
+
+#[derive(Clone)]
+pub struct UploadsConfig {
+    pub dir: String,
+    pub avatar_max_bytes: usize,
+}
+
+impl Config {
+    /// Loads and validates every layer, reporting all invalid keys at once.
+    pub fn load(args: &Args) -> Result<Self, ConfigError> {
+        resolve(args).map(|(config, _)| config)
+    }
+
+    pub fn profile_retention(&self) -> chrono::Duration {
+        chrono::Duration::days(self.profiles.retention_days)
+    }
+
+    pub fn uploads_url(&self) -> String {
+        format!(
+            "{}/uploads",
+            self.server.public_base_url.trim_end_matches('/')
+        )
+    }
+
+    fn from_layers(l: &mut Layers) -> Self {
+        let port = l.parse("server.port");
+        // Stored avatar URLs are built from this, so it has no default: a
+        // guessed host would end up persisted in every profile.
+        let public_base_url = l.required("server.public_base_url");
+        if !public_base_url.trim().is_empty() && !is_http_url(&public_base_url) {
+            l.invalid("server.public_base_url", "must be an absolute http(s) URL");
+        }
+
+        let max_connections = l.parse("database.max_connections");
+        let min_connections = l.parse("database.min_connections");
+        if max_connections == 0 {
+            l.invalid("database.max_connections", "must be at least 1");
+        } else if min_connections > max_connections {
+            l.invalid(
+                "database.min_connections",
+                format!("cannot exceed database.max_connections ({max_connections})"),
+            );
+        }
+
+        let allowed_origins = l.parse_with("cors.allowed_origins", cors::parse_origins);
+        let allow_credentials = l.parse("cors.allow_credentials");
+        if allow_credentials && allowed_origins.contains(&OriginPattern::Any) {
+            l.invalid(
+                "cors.allow_credentials",
+                "cannot be true while cors.allowed_origins contains `*`",
+            );
+        }
+
+        Config {
+            server: ServerConfig {
+                host: l.string("server.host"),
+                port,
+                public_base_url,
+                problem_json_errors: l.parse("server.problem_json_errors"),
+                shutdown_delay_secs: l.parse_in("server.shutdown_delay_secs", 0..=600),
+                shutdown_timeout_secs: l.parse_in("server.shutdown_timeout_secs", 1..=3600),
+            },
+            database: DatabaseConfig {
+                url: l.required("database.url"),
+                replica_url: l.optional("database.replica_url"),
+                read_your_writes_secs: l.parse_in("database.read_your_writes_secs", 0..=3600),
+                migrations: l.parse("database.migrations"),
+                max_connections,
+                min_connections,
+                acquire_timeout_secs: l.parse_in("database.acquire_timeout_secs", 1..=300),
+                idle_timeout_secs: l.parse_in("database.idle_timeout_secs", 1..=86_400),
+                max_lifetime_secs: l.parse_in("database.max_lifetime_secs", 1..=86_400),
+                statement_timeout_ms: l.parse_in("database.statement_timeout_ms", 0..=3_600_000),
+                slow_statement_ms: l.parse_in("database.slow_statement_ms", 0..=3_600_000),
+                log_statements: l
+                    .parse_with("database.log_statements", |level| {
+                        level.trim().parse().map(Some).map_err(|_| {
+                            format!(
+                                "{level:?} is not a level (off, error, warn, info, debug, trace)"
+                            )
+                        })
+                    })
+                    .unwrap_or(log::LevelFilter::Off),
+            },
+            auth: AuthConfig {
+                supabase_url: l.required("auth.supabase_url"),
+                jwt_secret: l.optional("auth.jwt_secret"),
+                jwks_ttl_secs: l.parse_in("auth.jwks_ttl_secs", 1..=86_400),
+            },
+            cors: CorsConfig {
+                allowed_origins,
+                allowed_methods: l.parse_with("cors.allowed_methods", cors::parse_methods),
+                allowed_headers: l.parse_with("cors.allowed_headers", cors::parse_headers),
+                max_age_secs: l.parse("cors.max_age_secs"),
+                allow_credentials,
+            },
+            logging: LoggingConfig {
+                format: l.parse("logging.format"),
+                filter: l.parse_with("logging.filter", |filter| {
+                    EnvFilter::try_new(filter)
+                        .map(|_| filter.to_string())
+                        .map_err(|e| format!("{filter:?} is not a valid filter: {e}"))
+                }),
+            },
+            metrics: MetricsConfig {
+                addr: l.optional("metrics.addr"),
+                token: l.optional("metrics.token"),
+            },
+            tracing: TracingConfig {
+                otlp_endpoint: l.optional("tracing.otlp_endpoint"),
+                service_name: l.string("tracing.service_name"),
+                sampling_ratio: l.parse_with("tracing.sampling_ratio", |ratio| {
+                    ratio
+                        .trim()
+                        .parse()
+                        .ok()
+                        .filter(|ratio| (0.0..=1.0).contains(ratio))
+                        .ok_or_else(|| format!("{ratio:?} is not a number between 0.0 and 1.0"))
+                }),
+            },
+            rate_limit: RateLimitConfig {
+                default: l.parse_with("rate_limit.default", |limit| match limit.trim() {
+                    "" => Ok(None),
+                    limit => limit.parse().map(Some),
+                }),
+                routes: l.parse_with("rate_limit.routes", rate_limit::parse_route_limits),
+                trust_forwarded_for: l.parse("rate_limit.trust_forwarded_for"),
+            },
+            profiles: ProfilesConfig {
+                retention_days: l.parse_in("profiles.retention_days", 0..=3650),
+                purge_interval_secs: l.parse_in("profiles.purge_interval_secs", 1..=604_800),
+            },
+            uploads: UploadsConfig {
+                dir: l.string("uploads.dir"),
+                avatar_max_bytes: l.parse_in("uploads.avatar_max_bytes", 1024..=50 * 1024 * 1024),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+impl Config {
+    /// The defaults plus the settings that have none, for tests elsewhere.
+    pub fn for_tests() -> Self {
+        let args = Args {
+            config: None,
+            set: Vec::new(),
+            print_config: false,
+        };
+        let vars = HashMap::from([
+            ("DATABASE_URL".into(), "postgres://localhost/test".into()),
+            ("SUPABASE_URL".into(), "http://127.0.0.1:9999".into()),
+            ("PUBLIC_BASE_URL".into(), "http://localhost:3000".into()),
+        ]);
+        resolve_with(&args, &vars).expect("test config is valid").0
+    }
+}
+
+/// The effective configuration as TOML, for `--print-config`. Secrets are
+/// masked and values that are not defaults name the layer they came from.
+pub fn describe(args: &Args) -> Result<String, ConfigError> {
+    resolve(args).map(|(_, layers)| layers.render())
+}
+
+fn resolve(args: &Args) -> Result<(Config, Layers), ConfigError> {
+    let vars: HashMap<String, String> = env::vars_os()
+        .filter_map(|(name, value)| Some((name.into_string().ok()?, value.into_string().ok()?)))
+        .collect();
+    resolve_with(args, &vars)
+}
+
+fn resolve_with(
+    args: &Args,
+    vars: &HashMap<String, String>,
+) -> Result<(Config, Layers), ConfigError> {
+    let mut layers = Layers::defaults(KEYS);
+    if let Some(path) = &args.config {
+        layers.merge_file(path);
+    }
+    layers.merge_env(vars);
+    layers.merge_overrides(&args.set);
+
+    let config = Config::from_layers(&mut layers);
+    layers.finish()?;
+    Ok((config, layers))
+}
+
+fn is_http_url(raw: &str) -> bool {
+    url::Url::parse(raw).is_ok_and(|url| matches!(url.scheme(), "http" | "https") && url.has_host())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use super::*;
+
+    const REQUIRED: [(&str, &str); 3] = [
+        ("DATABASE_URL", "postgres://app:hunter2@db:5432/app"),
+        ("SUPABASE_URL", "https://project.supabase.co"),
+        ("PUBLIC_BASE_URL", "https://api.example.com"),
+    ];
+
+    /// Writes `contents` to a file unique to the calling test.
+    fn temp_file(name: &str, contents: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("rest-api-{}-{name}", std::process::id()));
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn resolve(
+        file: Option<PathBuf>,
+        vars: &[(&str, &str)],
+        set: &[&str],
+    ) -> Result<(Config, Layers), ConfigError> {
+        let args = Args {
+            config: file,
+            set: set.iter().map(|s| s.to_string()).collect(),
+            print_config: false,
+        };
+        let vars = REQUIRED
+            .iter()
+            .chain(vars)
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        resolve_with(&args, &vars)
+    }
+
+    fn load(vars: &[(&str, &str)], set: &[&str]) -> Config {
+        resolve(None, vars, set).map(|(config, _)| config).unwrap()
+    }
+
+    fn invalid(file: Option<PathBuf>, vars: &[(&str, &str)], set: &[&str]) -> Vec<String> {
+        match resolve(file, vars, set) {
+            Ok(_) => panic!("configuration should be invalid"),
+            Err(e) => e.0.iter().map(ToString::to_string).collect(),
+        }
+    }
+
+    #[test]
+    fn defaults_apply_when_nothing_is_set() {
+        let config = load(&[], &[]);
+        assert_eq!(config.server.port, 3000);
+        assert_eq!(config.profiles.retention_days, 30);
+        assert!(config.auth.jwt_secret.is_none());
+    }
+
+    #[test]
+    fn later_layers_override_earlier_ones() {
+        let file = temp_file("precedence.toml", "[server]\nport = 4000\n");
+        let port = |vars: &[(&str, &str)], set: &[&str]| {
+            let (config, _) = resolve(Some(file.clone()), vars, set).unwrap();
+            config.server.port
+        };
+
+        assert_eq!(port(&[], &[]), 4000);
+        assert_eq!(port(&[("SERVER_PORT", "5000")], &[]), 5000);
+        assert_eq!(
+            port(
+                &[("SERVER_PORT", "5000"), ("APP_SERVER__PORT", "6000")],
+                &[]
+            ),
+            6000
+        );
+        assert_eq!(
+            port(&[("APP_SERVER__PORT", "6000")], &["server.port=7000"]),
+            7000
+        );
+        fs::remove_file(file).unwrap();
+    }
+
+    #[test]
+    fn file_variables_read_the_value_from_a_file() {
+        let secret = temp_file("jwt_secret", "s3cret\n");
+        let secret_path = secret.to_str().unwrap();
+
+        let config = load(&[("APP_AUTH__JWT_SECRET_FILE", secret_path)], &[]);
+        assert_eq!(config.auth.jwt_secret.as_deref(), Some("s3cret"));
+
+        let errors = invalid(
+            None,
+            &[
+                ("APP_AUTH__JWT_SECRET", "other"),
+                ("APP_AUTH__JWT_SECRET_FILE", secret_path),
+            ],
+            &[],
+        );
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].contains("both APP_AUTH__JWT_SECRET and APP_AUTH__JWT_SECRET_FILE"));
+        fs::remove_file(secret).unwrap();
+
+        let errors = invalid(None, &[("SUPABASE_JWT_SECRET_FILE", "/nonexistent")], &[]);
+        assert!(errors[0].starts_with("auth.jwt_secret (env SUPABASE_JWT_SECRET_FILE)"));
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected_in_every_layer() {
+        let file = temp_file("unknown.toml", "[server]\nprot = 4000\n");
+        let errors = invalid(
+            Some(file.clone()),
+            &[("APP_SERVER__HOTS", "x"), ("APP_ENV", "production")],
+            &["srever.port=1"],
+        );
+        fs::remove_file(file).unwrap();
+
+        assert_eq!(errors.len(), 3, "{errors:?}");
+        assert!(errors[0].starts_with("server.prot (file "));
+        assert_eq!(
+            errors[1],
+            "APP_SERVER__HOTS (env APP_SERVER__HOTS): unknown setting"
+        );
+        assert_eq!(errors[2], "srever.port (--set): unknown setting");
+    }
+
+    #[test]
+    fn all_errors_are_reported_together() {
+        let args = Args {
+            config: None,
+            set: vec!["server.port=80a".into()],
+            print_config: false,
+        };
+        let Err(e) = resolve_with(&args, &HashMap::new()) else {
+            panic!("configuration should be invalid");
+        };
+
+        let report = e.to_string();
+        assert!(
+            report.starts_with("invalid configuration (4 errors):"),
+            "{report}"
+        );
+        assert!(report.contains("server.port (--set): \"80a\" is invalid"));
+        assert!(report.contains("database.url: is required"));
+        assert!(report.contains("auth.supabase_url: is required"));
+        assert!(report.contains("server.public_base_url: is required"));
+    }
+
+    #[test]
+    fn public_base_url_must_be_an_http_url() {
+        for value in ["localhost:3000", "ftp://files.example.com", "/api"] {
+            let errors = invalid(None, &[("APP_SERVER__PUBLIC_BASE_URL", value)], &[]);
+            assert_eq!(
+                errors,
+                ["server.public_base_url (env APP_SERVER__PUBLIC_BASE_URL): \
+                  must be an absolute http(s) URL"]
+            );
+        }
+    }
+
+    #[test]
+    fn out_of_range_values_are_rejected() {
+        let errors = invalid(
+            None,
+            &[("APP_PROFILES__RETENTION_DAYS", "-1")],
+            &[
+                "profiles.purge_interval_secs=0",
+                "auth.jwks_ttl_secs=0",
+                "database.read_your_writes_secs=18446744073709551615",
+            ],
+        );
+
+        assert_eq!(
+            errors,
+            [
+                "database.read_your_writes_secs (--set): 18446744073709551615 is out of range (0 to 3600)",
+                "auth.jwks_ttl_secs (--set): 0 is out of range (1 to 86400)",
+                "profiles.retention_days (env APP_PROFILES__RETENTION_DAYS): -1 is out of range (0 to 3650)",
+                "profiles.purge_interval_secs (--set): 0 is out of range (1 to 604800)",
+            ]
+        );
+    }
+
+    #[test]
+    fn timeouts_and_sizes_are_range_checked() {
+        let errors = invalid(
+            None,
+            &[("SHUTDOWN_TIMEOUT_SECS", "0")],
+            &[
+                "database.acquire_timeout_secs=0",
+                "database.idle_timeout_secs=0",
+                "database.max_lifetime_secs=0",
+                "database.statement_timeout_ms=86400000",
+                "uploads.avatar_max_bytes=0",
+            ],
+        );
+
+        assert_eq!(
+            errors,
+            [
+                "server.shutdown_timeout_secs (env SHUTDOWN_TIMEOUT_SECS): 0 is out of range (1 to 3600)",
+                "database.acquire_timeout_secs (--set): 0 is out of range (1 to 300)",
+                "database.idle_timeout_secs (--set): 0 is out of range (1 to 86400)",
+                "database.max_lifetime_secs (--set): 0 is out of range (1 to 86400)",
+                "database.statement_timeout_ms (--set): 86400000 is out of range (0 to 3600000)",
+                "uploads.avatar_max_bytes (--set): 0 is out of range (1024 to 52428800)",
+            ]
+        );
+
+        // 0 turns the statement timeout and slow statement logging off.
+        let config = load(
+            &[],
+            &[
+                "database.statement_timeout_ms=0",
+                "database.slow_statement_ms=0",
+            ],
+        );
+        assert_eq!(config.database.statement_timeout_ms, 0);
+        assert_eq!(config.database.slow_statement_ms, 0);
+    }
+
+    #[test]
+    fn printed_config_masks_secrets() {
+        let (_, layers) = resolve(
+            None,
+            &[("METRICS_TOKEN", "scrape-token")],
+            &["auth.jwt_secret=jwt-secret"],
+        )
+        .unwrap();
+        let printed = layers.render();
+
+        assert!(!printed.contains("hunter2"));
+        assert!(!printed.contains("scrape-token"));
+        assert!(!printed.contains("jwt-secret"));
+        assert!(
+            printed.contains("url = \"postgres://app:********@db:5432/app\"  # env DATABASE_URL")
+        );
+        assert!(printed.contains("jwt_secret = \"********\"  # --set"));
+        assert!(printed.contains("port = 3000\n"));
+    }
+}
@@ -0,0 +1,312 @@
+use std::str::FromStr;
+use std::time::Duration;
+
+use axum::http::header::{ETAG, HeaderName, RETRY_AFTER};
+use axum::http::{HeaderValue, Method};
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+use crate::config::Config;
+use crate::rate_limit::RATE_LIMIT_HEADERS;
+
+/// An entry of `cors.allowed_origins`: `*`, an exact origin
+/// (`https://app.example.com`) or a subdomain wildcard
+/// (`https://*.example.com`, which does not match `https://example.com`).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum OriginPattern {
+    Any,
+    Exact(String),
+    Subdomain { scheme: String, suffix: String },
+}
+
+impl OriginPattern {
+    pub fn matches(&self, origin: &str) -> bool {
+        let origin = origin.to_ascii_lowercase();
+        match self {
+            OriginPattern::Any => true,
+            OriginPattern::Exact(exact) => origin == *exact,
+            OriginPattern::Subdomain { scheme, suffix } => origin
+                .strip_prefix(scheme.as_str())
+                .and_then(|rest| rest.strip_prefix("://"))
+                .and_then(|host| host.strip_suffix(suffix.as_str()))
+                .and_then(|labels| labels.strip_suffix('.'))
+                .is_some_and(|labels| {
+                    !labels.is_empty()
+                        && !labels.starts_with('.')
+                        && labels
+                            .chars()
+                            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+                }),
+        }
+    }
+}
+
+impl FromStr for OriginPattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "*" {
+            return Ok(OriginPattern::Any);
+        }
+
+        let origin = s.trim_end_matches('/').to_ascii_lowercase();
+        let Some((scheme, host)) = origin.split_once("://") else {
+            return Err(format!("{s:?} is not an origin (scheme://host[:port])"));
+        };
+
+        if !matches!(scheme, "http" | "https") {
+            return Err(format!("{s:?} must use http or https"));
+        }
+
+        let valid_host = |host: &str| {
+            !host.is_empty()
+                && !host.contains(['/', '?', '#', '@', '*'])
+                && HeaderValue::from_str(host).is_ok()
+        };
+
+        match host.strip_prefix("*.") {
+            Some(suffix) if valid_host(suffix) => Ok(OriginPattern::Subdomain {
+                scheme: scheme.to_string(),
+                suffix: suffix.to_string(),
+            }),
+            None if valid_host(host) => Ok(OriginPattern::Exact(origin)),
+            _ => Err(format!(
+                "{s:?} is not a valid origin; wildcards are only allowed as a leading `*.`"
+            )),
+        }
+    }
+}
+
+/// Builds the CORS layer from the configured policy. Origins that match no
+/// pattern get no `Access-Control-Allow-*` headers, so browsers block them;
+/// with no origins configured, cross-origin requests are not allowed at all.
+///
+/// `ETag`, `X-Request-Id`, `Retry-After` and the `RateLimit-*` headers are
+/// exposed so browser clients can send conditional requests, report request
+/// ids and pace themselves.
+pub fn layer(config: &Config) -> CorsLayer {
+    let origins = config.cors.allowed_origins.clone();
+    let allow_origin = if origins.contains(&OriginPattern::Any) {
+        AllowOrigin::any()
+    } else {
+        AllowOrigin::predicate(move |origin, _| {
+            origin
+                .to_str()
+                .is_ok_and(|origin| origins.iter().any(|p| p.matches(origin)))
+        })
+    };
+
+    CorsLayer::new()
+        .allow_origin(allow_origin)
+        .allow_methods(config.cors.allowed_methods.clone())
+        .allow_headers(config.cors.allowed_headers.clone())
+        .expose_headers(exposed_headers())
+        .allow_credentials(config.cors.allow_credentials)
+        .max_age(Duration::from_secs(config.cors.max_age_secs))
+}
+
+fn exposed_headers() -> Vec<HeaderName> {
+    let rate_limit = RATE_LIMIT_HEADERS.map(HeaderName::from_static);
+    [ETAG, HeaderName::from_static("x-request-id"), RETRY_AFTER]
+        .into_iter()
+        .chain(rate_limit)
+        .collect()
+}
+
+pub fn parse_methods(list: &str) -> Result<Vec<Method>, String> {
+    split_list(list)
+        .map(|m| {
+            Method::from_bytes(m.to_ascii_uppercase().as_bytes())
+                .map_err(|_| format!("{m:?} is not an HTTP method"))
+        })
+        .collect()
+}
+
+pub fn parse_headers(list: &str) -> Result<Vec<HeaderName>, String> {
+    split_list(list)
+        .map(|h| HeaderName::from_str(h).map_err(|_| format!("{h:?} is not a header name")))
+        .collect()
+}
+
+pub fn parse_origins(list: &str) -> Result<Vec<OriginPattern>, String> {
+    split_list(list).map(str::parse).collect()
+}
+
+fn split_list(list: &str) -> impl Iterator<Item = &str> {
+    list.split(',').map(str::trim).filter(|s| !s.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::Router;
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode, header};
+    use axum::response::Response;
+    use axum::routing::get;
+    use tower::ServiceExt;
+
+    use super::*;
+
+    fn pattern(s: &str) -> OriginPattern {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_origins() {
+        assert_eq!(pattern("*"), OriginPattern::Any);
+        assert_eq!(
+            pattern("HTTPS://App.Example.com/"),
+            OriginPattern::Exact("https://app.example.com".into())
+        );
+        assert_eq!(
+            pattern("https://*.example.com:8443"),
+            OriginPattern::Subdomain {
+                scheme: "https".into(),
+                suffix: "example.com:8443".into(),
+            }
+        );
+
+        for invalid in [
+            "example.com",
+            "ftp://example.com",
+            "https://",
+            "https://app.example.com/path",
+            "https://user@example.com",
+            "https://app.*.example.com",
+            "https://*example.com",
+            "https://*.",
+        ] {
+            assert!(invalid.parse::<OriginPattern>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn exact_origins_match_scheme_host_and_port() {
+        let exact = pattern("http://localhost:5173");
+        assert!(exact.matches("http://localhost:5173"));
+        assert!(exact.matches("HTTP://LOCALHOST:5173"));
+        assert!(!exact.matches("http://localhost"));
+        assert!(!exact.matches("http://localhost:3000"));
+        assert!(!exact.matches("https://localhost:5173"));
+    }
+
+    #[test]
+    fn subdomain_wildcards_match_subdomains_only() {
+        let wildcard = pattern("https://*.example.com");
+        assert!(wildcard.matches("https://app.example.com"));
+        assert!(wildcard.matches("https://a.b.example.com"));
+        assert!(!wildcard.matches("https://example.com"));
+        assert!(!wildcard.matches("https://.example.com"));
+        assert!(!wildcard.matches("https://evil-example.com"));
+        assert!(!wildcard.matches("https://app.example.com.evil.com"));
+        assert!(!wildcard.matches("http://app.example.com"));
+        assert!(!wildcard.matches("https://app.example.com:8443"));
+        assert!(!wildcard.matches("https://user@app.example.com"));
+
+        let with_port = pattern("https://*.example.com:8443");
+        assert!(with_port.matches("https://app.example.com:8443"));
+        assert!(!with_port.matches("https://app.example.com"));
+    }
+
+    async fn preflight(origins: &str, credentials: bool, origin: &str) -> Response {
+        let mut config = Config::for_tests();
+        config.cors.allowed_origins = parse_origins(origins).unwrap();
+        config.cors.allow_credentials = credentials;
+
+        let app: Router = Router::new()
+            .route("/users/me", get(|| async {}))
+            .layer(layer(&config));
+        let req = Request::builder()
+            .method(Method::OPTIONS)
+            .uri("/users/me")
+            .header(header::ORIGIN, origin)
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "PATCH")
+            .header(
+                header::ACCESS_CONTROL_REQUEST_HEADERS,
+                "authorization,if-match",
+            )
+            .body(Body::empty())
+            .unwrap();
+        app.oneshot(req).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn preflight_from_an_allowed_origin_is_answered() {
+        let res = preflight(
+            "https://app.example.com,https://*.preview.example.com",
+            true,
+            "https://pr-1.preview.example.com",
+        )
+        .await;
+        let headers = res.headers();
+
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(
+            headers[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://pr-1.preview.example.com"
+        );
+        assert_eq!(headers[header::ACCESS_CONTROL_ALLOW_CREDENTIALS], "true");
+        assert_eq!(headers[header::ACCESS_CONTROL_MAX_AGE], "600");
+        let methods = headers[header::ACCESS_CONTROL_ALLOW_METHODS]
+            .to_str()
+            .unwrap();
+        assert!(methods.contains("PATCH"), "{methods}");
+        let allowed = headers[header::ACCESS_CONTROL_ALLOW_HEADERS]
+            .to_str()
+            .unwrap();
+        assert!(allowed.contains("if-match"), "{allowed}");
+    }
+
+    #[tokio::test]
+    async fn preflight_from_a_rejected_origin_gets_no_cors_headers() {
+        for origin in ["https://evil.com", "http://app.example.com"] {
+            let res = preflight("https://app.example.com", false, origin).await;
+            assert!(
+                !res.headers()
+                    .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN),
+                "{origin}"
+            );
+        }
+
+        let res = preflight("", false, "https://app.example.com").await;
+        assert!(
+            !res.headers()
+                .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+        );
+    }
+
+    #[tokio::test]
+    async fn any_origin_is_answered_with_a_wildcard() {
+        let res = preflight("*", false, "https://anywhere.test").await;
+        assert_eq!(res.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN], "*");
+    }
+
+    #[tokio::test]
+    async fn responses_expose_caching_request_id_and_rate_limit_headers() {
+        let mut config = Config::for_tests();
+        config.cors.allowed_origins = parse_origins("https://app.example.com").unwrap();
+
+        let app: Router = Router::new()
+            .route("/users/me", get(|| async {}))
+            .layer(layer(&config));
+        let req = Request::builder()
+            .uri("/users/me")
+            .header(header::ORIGIN, "https://app.example.com")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+
+        let exposed = res.headers()[header::ACCESS_CONTROL_EXPOSE_HEADERS]
+            .to_str()
+            .unwrap();
+        for name in [
+            "etag",
+            "x-request-id",
+            "retry-after",
+            "ratelimit-limit",
+            "ratelimit-remaining",
+            "ratelimit-reset",
+        ] {
+            assert!(exposed.contains(name), "{name} missing from {exposed}");
+        }
+    }
+}
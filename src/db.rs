@@ -1,14 +1,413 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
 use migration::{Migrator, MigratorTrait};
-use sea_orm::{Database, DatabaseConnection, DbErr};
+use sea_orm::{
+    ConnectOptions, ConnectionTrait, Database, DatabaseConnection, DatabaseTransaction, DbBackend,
+    DbErr, Statement, TransactionTrait,
+};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::config::{Config, DatabaseConfig, MigrationMode};
+use crate::errors::AppError;
+
+/// Advisory lock held while migrations are applied, so replicas starting
+/// together migrate one at a time. Any fixed key works as long as every
+/// instance uses the same one.
+const MIGRATION_LOCK: i64 = 0x7265_7374_2d61_7069;
+
+/// The primary pool and, when configured, a read replica pool. Cheap to
+/// clone; clones share the pools and the record of recent writers.
+///
+/// Reads made on a user's behalf go to the replica, except for a short
+/// while after that user wrote through `write_for`, so they see their own
+/// changes despite replication lag. The record is per instance; requests
+/// balanced to another instance right after a write may still read stale
+/// data.
+#[derive(Clone)]
+pub struct Pools {
+    primary: DatabaseConnection,
+    replica: Option<DatabaseConnection>,
+    read_your_writes: Duration,
+    recent_writers: Arc<Mutex<HashMap<String, Instant>>>,
+}
+
+const MIN_PRUNE_AT: usize = 1024;
+
+impl Pools {
+    /// The primary, for writes not made on behalf of a user (background
+    /// jobs), migrations and health checks. Writes to a user's data made
+    /// here should be followed by `record_write`.
+    pub fn primary(&self) -> &DatabaseConnection {
+        &self.primary
+    }
+
+    pub fn replica(&self) -> Option<&DatabaseConnection> {
+        self.replica.as_ref()
+    }
+
+    /// The primary, for a write made on behalf of `user_id`, or a read that
+    /// a write depends on. Routes that user's reads to the primary for the
+    /// read-your-writes window.
+    pub fn write_for(&self, user_id: &str) -> &DatabaseConnection {
+        self.record_write(user_id);
+        &self.primary
+    }
+
+    /// Routes `user_id`'s reads to the primary for the read-your-writes
+    /// window. `write_for` and `begin_for` do this for the caller; a write to
+    /// someone else's data, such as an operator's edit, records it for the
+    /// user whose data changed.
+    pub fn record_write(&self, user_id: &str) {
+        if self.replica.is_some() {
+            let now = Instant::now();
+            let mut writers = self.recent_writers.lock().expect("recent writers poisoned");
+            if writers.len() >= MIN_PRUNE_AT {
+                writers.retain(|_, until| *until > now);
+            }
+            writers.insert(user_id.to_string(), now + self.read_your_writes);
+        }
+    }
+
+    /// Begins a transaction on the primary for a unit of work made on behalf
+    /// of `user_id`, for handlers whose steps must succeed or fail together.
+    /// Pass it to the services in place of a connection and `commit` it at
+    /// the end; returning early, with `?` or otherwise, drops it and rolls
+    /// everything back.
+    pub async fn begin_for(&self, user_id: &str) -> Result<DatabaseTransaction, DbErr> {
+        self.write_for(user_id).begin().await
+    }
+
+    /// Runs `work` in a transaction begun with `begin_for`, committing it if
+    /// `work` succeeds and rolling it back if it fails. Anything to do only
+    /// once the changes are committed, such as removing files, goes after.
+    pub async fn transaction_for<T>(
+        &self,
+        user_id: &str,
+        work: impl AsyncFnOnce(&DatabaseTransaction) -> Result<T, AppError>,
+    ) -> Result<T, AppError> {
+        let txn = self.begin_for(user_id).await?;
+        let value = work(&txn).await?;
+        txn.commit().await?;
+        Ok(value)
+    }
+
+    /// The pool for a read made on behalf of `user_id`: the replica, unless
+    /// there is none or the user wrote within the read-your-writes window.
+    pub fn read_for(&self, user_id: &str) -> &DatabaseConnection {
+        let Some(replica) = &self.replica else {
+            return &self.primary;
+        };
+
+        let wrote_recently = self
+            .recent_writers
+            .lock()
+            .expect("recent writers poisoned")
+            .get(user_id)
+            .is_some_and(|until| *until > Instant::now());
+        if wrote_recently {
+            &self.primary
+        } else {
+            replica
+        }
+    }
+
+    /// Pools with only `primary`, for tests. `DatabaseConnection::Disconnected`
+    /// suits tests that never query.
+    #[cfg(test)]
+    pub fn for_tests(primary: DatabaseConnection) -> Self {
+        Pools {
+            primary,
+            replica: None,
+            read_your_writes: Duration::ZERO,
+            recent_writers: Arc::default(),
+        }
+    }
+
+    pub async fn close(self) -> Result<(), DbErr> {
+        if let Some(replica) = self.replica {
+            replica.close().await?;
+        }
+        self.primary.close().await
+    }
+}
+
+/// Connects the primary and, when `database.replica_url` is set, the
+/// replica. Both pools use the same settings.
+pub async fn connect(config: &Config) -> Result<Pools, DbErr> {
+    let database = &config.database;
+    let primary = Database::connect(connect_options(&database.url, database)).await?;
+    log_pool("primary", &primary, database);
+
+    let replica = match &database.replica_url {
+        Some(url) => {
+            let replica = Database::connect(connect_options(url, database)).await?;
+            log_pool("replica", &replica, database);
+            Some(replica)
+        }
+        None => None,
+    };
+
+    Ok(Pools {
+        primary,
+        replica,
+        read_your_writes: Duration::from_secs(database.read_your_writes_secs),
+        recent_writers: Arc::default(),
+    })
+}
+
+fn log_pool(role: &str, db: &DatabaseConnection, config: &DatabaseConfig) {
+    let pool = PoolStatus::of(db, config);
+    tracing::info!(
+        pool = role,
+        max_connections = pool.max_connections,
+        min_connections = pool.min_connections,
+        acquire_timeout_secs = pool.acquire_timeout_secs,
+        idle_timeout_secs = pool.idle_timeout_secs,
+        max_lifetime_secs = pool.max_lifetime_secs,
+        statement_timeout_ms = pool.statement_timeout_ms,
+        slow_statement_ms = config.slow_statement_ms,
+        log_statements = %config.log_statements,
+        "Connected to database"
+    );
+}
+
+/// Brings the schema up to date, or checks that it is, as `mode` says.
+pub async fn migrate(db: &DatabaseConnection, mode: MigrationMode) -> Result<(), DbErr> {
+    match mode {
+        MigrationMode::Apply => apply_migrations(db).await,
+        MigrationMode::Verify => {
+            let pending = pending_migrations(db).await?;
+            if pending.is_empty() {
+                tracing::info!(latest = %latest_migration(), "Database schema is up to date");
+                Ok(())
+            } else {
+                Err(DbErr::Migration(format!(
+                    "not applied: {}; run `make migrate` or start one instance with \
+                     database.migrations=apply",
+                    pending.join(", ")
+                )))
+            }
+        }
+        MigrationMode::Skip => {
+            tracing::warn!("Skipping migration check (database.migrations=skip)");
+            Ok(())
+        }
+    }
+}
+
+/// Applies pending migrations in one transaction that holds the migration
+/// lock. The lock is released when the transaction ends, even if this
+/// process dies, and pending migrations are listed only once it is held,
+/// so an instance that waited sees the work of the one that migrated.
+async fn apply_migrations(db: &DatabaseConnection) -> Result<(), DbErr> {
+    let txn = db.begin().await?;
+    // Neither the wait for the lock nor the migrations themselves are
+    // bounded by the pool's statement_timeout.
+    txn.execute_unprepared("SET LOCAL statement_timeout = 0")
+        .await?;
+
+    let locked = txn
+        .query_one(Statement::from_sql_and_values(
+            DbBackend::Postgres,
+            "SELECT pg_try_advisory_xact_lock($1)",
+            [MIGRATION_LOCK.into()],
+        ))
+        .await?
+        .map(|row| row.try_get_by_index::<bool>(0))
+        .transpose()?
+        .unwrap_or(false);
+    if !locked {
+        tracing::info!("Another instance is migrating; waiting for the migration lock");
+        txn.execute(Statement::from_sql_and_values(
+            DbBackend::Postgres,
+            "SELECT pg_advisory_xact_lock($1)",
+            [MIGRATION_LOCK.into()],
+        ))
+        .await?;
+    }
+
+    let pending = pending_migrations(&txn).await?;
+    if pending.is_empty() {
+        txn.commit().await?;
+        tracing::info!(latest = %latest_migration(), "Database schema is up to date");
+        return Ok(());
+    }
+
+    tracing::info!(migrations = ?pending, "Applying pending migrations");
+    let start = Instant::now();
+    Migrator::up(&txn, None).await?;
+    txn.commit().await?;
+    tracing::info!(
+        migrations = ?pending,
+        elapsed_ms = start.elapsed().as_millis() as u64,
+        "Applied pending migrations"
+    );
+
+    Ok(())
+}
+
+/// Name of the newest migration this build knows about.
+pub fn latest_migration() -> String {
+    Migrator::migrations()
+        .last()
+        .map(|m| m.name().to_string())
+        .unwrap_or_default()
+}
+
+/// Names of known migrations that have not been applied, oldest first.
+/// Unlike `Migrator::get_pending_migrations` this only reads, so it works
+/// for database users without `CREATE` rights.
+pub async fn pending_migrations(db: &impl ConnectionTrait) -> Result<Vec<String>, DbErr> {
+    let installed = db
+        .query_one(Statement::from_string(
+            DbBackend::Postgres,
+            "SELECT to_regclass('seaql_migrations') IS NOT NULL",
+        ))
+        .await?
+        .map(|row| row.try_get_by_index::<bool>(0))
+        .transpose()?
+        .unwrap_or(false);
+
+    let applied: HashSet<String> = if installed {
+        db.query_all(Statement::from_string(
+            DbBackend::Postgres,
+            "SELECT version FROM seaql_migrations",
+        ))
+        .await?
+        .iter()
+        .map(|row| row.try_get_by_index(0))
+        .collect::<Result<_, _>>()?
+    } else {
+        HashSet::new()
+    };
+
+    Ok(Migrator::migrations()
+        .iter()
+        .map(|m| m.name().to_string())
+        .filter(|name| !applied.contains(name))
+        .collect())
+}
+
+/// The database in `DATABASE_URL` (also read from `.env`), migrated, for
+/// tests that need Postgres. They fail rather than skip without one, so a
+/// missing database cannot pass for a green run.
+#[cfg(test)]
+pub async fn test_database() -> DatabaseConnection {
+    dotenvy::dotenv().ok();
+    let url = std::env::var("DATABASE_URL")
+        .expect("DATABASE_URL must point at a Postgres database; `make db` starts one");
+    let db = Database::connect(url).await.unwrap();
+    migrate(&db, MigrationMode::Apply).await.unwrap();
+    db
+}
+
+fn connect_options(url: &str, config: &DatabaseConfig) -> ConnectOptions {
+    let mut options = ConnectOptions::new(url);
+    options
+        .max_connections(config.max_connections)
+        .min_connections(config.min_connections)
+        .acquire_timeout(Duration::from_secs(config.acquire_timeout_secs))
+        .idle_timeout(Duration::from_secs(config.idle_timeout_secs))
+        .max_lifetime(Duration::from_secs(config.max_lifetime_secs))
+        .sqlx_logging(true)
+        .sqlx_logging_level(config.log_statements);
+
+    // Statements over the threshold are logged as warnings through tracing,
+    // with the statement and its duration, whatever `log_statements` is.
+    if config.slow_statement_ms > 0 {
+        options.sqlx_slow_statements_logging_settings(
+            log::LevelFilter::Warn,
+            Duration::from_millis(config.slow_statement_ms),
+        );
+    }
+
+    // Sent as a startup parameter, so every pooled connection gets it
+    // without an extra round trip. Postgres reads 0 as no timeout.
+    let statement_timeout = config.statement_timeout_ms;
+    options.map_sqlx_postgres_opts(move |opts| {
+        opts.options([("statement_timeout", statement_timeout)])
+    });
+
+    options
+}
+
+/// Effective pool settings, read back from the pool, and its current usage.
+#[derive(Serialize, ToSchema)]
+pub struct PoolStatus {
+    /// Open connections, idle or in use.
+    pub size: u32,
+    pub idle: usize,
+    pub max_connections: u32,
+    pub min_connections: u32,
+    pub acquire_timeout_secs: u64,
+    /// None when idle connections are never closed.
+    pub idle_timeout_secs: Option<u64>,
+    /// None when connections are never recycled.
+    pub max_lifetime_secs: Option<u64>,
+    /// Server-side limit on each statement; 0 means none.
+    pub statement_timeout_ms: u64,
+}
+
+impl PoolStatus {
+    pub fn of(db: &DatabaseConnection, config: &DatabaseConfig) -> Self {
+        let pool = db.get_postgres_connection_pool();
+        let options = pool.options();
+
+        PoolStatus {
+            size: pool.size(),
+            idle: pool.num_idle(),
+            max_connections: options.get_max_connections(),
+            min_connections: options.get_min_connections(),
+            acquire_timeout_secs: options.get_acquire_timeout().as_secs(),
+            idle_timeout_secs: options.get_idle_timeout().map(|d| d.as_secs()),
+            max_lifetime_secs: options.get_max_lifetime().map(|d| d.as_secs()),
+            statement_timeout_ms: config.statement_timeout_ms,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_replica() -> Pools {
+        Pools {
+            replica: Some(DatabaseConnection::Disconnected),
+            read_your_writes: Duration::from_secs(60),
+            ..Pools::for_tests(DatabaseConnection::Disconnected)
+        }
+    }
+
+    fn reads_primary(pools: &Pools, user_id: &str) -> bool {
+        std::ptr::eq(pools.read_for(user_id), pools.primary())
+    }
+
+    #[test]
+    fn reads_go_to_the_primary_after_a_write() {
+        let pools = with_replica();
+        assert!(!reads_primary(&pools, "user-1"));
+
+        pools.write_for("user-1");
+        assert!(reads_primary(&pools, "user-1"));
+        assert!(!reads_primary(&pools, "user-2"));
+    }
 
-use crate::config::Config;
+    #[test]
+    fn writes_for_another_user_are_recorded_for_them() {
+        let pools = with_replica();
 
-pub async fn connect(config: &Config) -> Result<DatabaseConnection, DbErr> {
-    let db = Database::connect(&config.database_url).await?;
-    tracing::info!("Connected to database");
+        pools.record_write("user-1");
 
-    Migrator::up(&db, None).await?;
-    tracing::info!("Migrations applied successfully");
+        assert!(reads_primary(&pools, "user-1"));
+        assert!(!reads_primary(&pools, "admin-1"));
+    }
 
-    Ok(db)
+    #[test]
+    fn without_a_replica_every_read_goes_to_the_primary() {
+        let pools = Pools::for_tests(DatabaseConnection::Disconnected);
+        assert!(reads_primary(&pools, "user-1"));
+    }
 }
@@ -1,21 +1,68 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
 use axum::Json;
-use axum::http::StatusCode;
+use axum::extract::multipart::MultipartRejection;
+use axum::extract::rejection::{BytesRejection, PathRejection, QueryRejection};
+use axum::extract::{Request, State};
+use axum::http::{HeaderValue, StatusCode, header};
+use axum::middleware::Next;
 use axum::response::{IntoResponse, Response};
-use serde_json::json;
+use serde::Serialize;
+use serde_json::Value;
+use tower_http::request_id::RequestId;
+use utoipa::ToSchema;
+use validator::{ValidationError, ValidationErrors, ValidationErrorsKind};
+
+use crate::AppState;
+use crate::telemetry::redact;
+
+/// A JSON body that could not be deserialized, with the path of the member
+/// that failed.
+pub type JsonError = serde_path_to_error::Error<serde_json::Error>;
+
+pub const PROBLEM_JSON_CONTENT_TYPE: &str = "application/problem+json";
 
 #[derive(thiserror::Error, Debug)]
 pub enum AppError {
-    #[error("not found: {0}")]
-    NotFound(String),
+    #[error("profile not found")]
+    ProfileNotFound,
 
     #[error("bad request: {0}")]
     BadRequest(String),
 
+    /// Missing or malformed credentials.
     #[error("unauthorized: {0}")]
     Unauthorized(String),
 
+    /// A bearer token that was present but could not be verified.
+    #[error("invalid token: {0}")]
+    InvalidToken(String),
+
+    #[error("token has expired")]
+    TokenExpired,
+
+    #[error("forbidden: {0}")]
+    Forbidden(String),
+
+    #[error("precondition failed: {0}")]
+    PreconditionFailed(String),
+
+    #[error("payload too large: {0}")]
+    PayloadTooLarge(String),
+
+    #[error("unsupported media type: {0}")]
+    UnsupportedMediaType(String),
+
+    /// The client's rate limit is exhausted; `retry_after` is in seconds.
+    #[error("rate limit exceeded")]
+    RateLimited { retry_after: u64 },
+
     #[error("{0}")]
-    Validation(#[from] validator::ValidationErrors),
+    Validation(#[from] ValidationErrors),
+
+    #[error("invalid JSON body: {0}")]
+    InvalidJson(#[from] JsonError),
 
     #[error("internal error: {0}")]
     Internal(String),
@@ -24,30 +71,516 @@ pub enum AppError {
     Database(#[from] sea_orm::DbErr),
 }
 
-impl IntoResponse for AppError {
-    fn into_response(self) -> Response {
-        let (status, message) = match &self {
-            AppError::NotFound(msg) => (StatusCode::NOT_FOUND, msg.clone()),
-            AppError::BadRequest(msg) => (StatusCode::BAD_REQUEST, msg.clone()),
-            AppError::Unauthorized(msg) => (StatusCode::UNAUTHORIZED, msg.clone()),
-            AppError::Validation(errors) => (StatusCode::UNPROCESSABLE_ENTITY, errors.to_string()),
-            AppError::Internal(msg) => (StatusCode::INTERNAL_SERVER_ERROR, msg.clone()),
+/// Stable machine-readable error codes. Clients should branch on these rather
+/// than on messages, which are meant for humans and may change. Existing
+/// codes are never renamed; new ones may be added.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    BadRequest,
+    InvalidJson,
+    ValidationFailed,
+    Unauthorized,
+    InvalidToken,
+    TokenExpired,
+    Forbidden,
+    ProfileNotFound,
+    PreconditionFailed,
+    PayloadTooLarge,
+    UnsupportedMediaType,
+    RateLimited,
+    InternalError,
+}
+
+/// The code as it is serialized, so `rename_all` is the only place it is
+/// spelled out.
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match serde_json::to_value(self) {
+            Ok(Value::String(code)) => f.write_str(&code),
+            _ => Err(fmt::Error),
+        }
+    }
+}
+
+impl ErrorCode {
+    /// Short summary that is the same for every occurrence of the code, used
+    /// as the problem `title`.
+    pub fn title(self) -> &'static str {
+        match self {
+            ErrorCode::BadRequest => "Bad request",
+            ErrorCode::InvalidJson => "Invalid JSON body",
+            ErrorCode::ValidationFailed => "Validation failed",
+            ErrorCode::Unauthorized => "Authentication required",
+            ErrorCode::InvalidToken => "Invalid token",
+            ErrorCode::TokenExpired => "Token expired",
+            ErrorCode::Forbidden => "Forbidden",
+            ErrorCode::ProfileNotFound => "Profile not found",
+            ErrorCode::PreconditionFailed => "Precondition failed",
+            ErrorCode::PayloadTooLarge => "Payload too large",
+            ErrorCode::UnsupportedMediaType => "Unsupported media type",
+            ErrorCode::RateLimited => "Too many requests",
+            ErrorCode::InternalError => "Internal server error",
+        }
+    }
+}
+
+/// JSON envelope errors are rendered as by default.
+#[derive(Serialize, ToSchema)]
+pub struct ErrorResponse {
+    pub error: ErrorBody,
+}
+
+#[derive(Clone, Serialize, ToSchema)]
+pub struct ErrorBody {
+    /// HTTP status code, repeated for clients that only see the body.
+    #[schema(example = 404)]
+    pub status: u16,
+    pub code: ErrorCode,
+    #[schema(example = "profile not found")]
+    pub message: String,
+    /// One entry per offending field, present on `422` validation errors and
+    /// `400` invalid JSON bodies.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub details: Vec<ErrorDetail>,
+    /// Id of the request, also sent as `X-Request-Id`. Quote it when reporting
+    /// an error so it can be found in the server logs.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(example = "5f1c3e0a-8d0b-4a8e-9c1e-2b7f6d4a9e31")]
+    pub request_id: Option<String>,
+}
+
+/// RFC 7807 rendering of an error, used instead of `ErrorResponse` when
+/// `server.problem_json_errors` is enabled.
+#[derive(Serialize, ToSchema)]
+pub struct ProblemDetails {
+    /// Identifies the problem type: `/problems/{code}`.
+    #[serde(rename = "type")]
+    #[schema(example = "/problems/profile_not_found")]
+    pub problem_type: String,
+    #[schema(example = "Profile not found")]
+    pub title: String,
+    #[schema(example = 404)]
+    pub status: u16,
+    #[schema(example = "profile not found")]
+    pub detail: String,
+    /// Path of the request that failed.
+    #[schema(example = "/users/me")]
+    pub instance: String,
+    pub code: ErrorCode,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub details: Vec<ErrorDetail>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+impl ProblemDetails {
+    fn new(error: ErrorBody, instance: String) -> Self {
+        ProblemDetails {
+            problem_type: format!("/problems/{}", error.code),
+            title: error.code.title().to_string(),
+            status: error.status,
+            detail: error.message,
+            instance,
+            code: error.code,
+            details: error.details,
+            request_id: error.request_id,
+        }
+    }
+}
+
+#[derive(Clone, Serialize, ToSchema)]
+pub struct ErrorDetail {
+    /// Path of the offending member, e.g. `bio` or `links[0].url`; empty when
+    /// the error concerns the whole body.
+    #[schema(example = "display_name")]
+    pub field: String,
+    /// Machine-readable reason: a validator code such as `length`, `range` or
+    /// `email`, or for JSON bodies one of `invalid_type`, `invalid_value`,
+    /// `missing_field`, `unknown_field`, `unknown_variant` and `malformed_json`.
+    #[schema(example = "length")]
+    pub code: String,
+    #[schema(example = "length must be between 2 and 100")]
+    pub message: String,
+    /// Parameters of the failed rule, e.g. `{"min": 2, "max": 100}` or
+    /// `{"expected": "a string"}`.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub params: BTreeMap<String, Value>,
+}
+
+impl AppError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            AppError::ProfileNotFound => StatusCode::NOT_FOUND,
+            AppError::BadRequest(_) | AppError::InvalidJson(_) => StatusCode::BAD_REQUEST,
+            AppError::Unauthorized(_) | AppError::InvalidToken(_) | AppError::TokenExpired => {
+                StatusCode::UNAUTHORIZED
+            }
+            AppError::Forbidden(_) => StatusCode::FORBIDDEN,
+            AppError::PreconditionFailed(_) => StatusCode::PRECONDITION_FAILED,
+            AppError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            AppError::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            AppError::RateLimited { .. } => StatusCode::TOO_MANY_REQUESTS,
+            AppError::Validation(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            AppError::Internal(_) | AppError::Database(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            AppError::ProfileNotFound => ErrorCode::ProfileNotFound,
+            AppError::BadRequest(_) => ErrorCode::BadRequest,
+            AppError::Unauthorized(_) => ErrorCode::Unauthorized,
+            AppError::InvalidToken(_) => ErrorCode::InvalidToken,
+            AppError::TokenExpired => ErrorCode::TokenExpired,
+            AppError::Forbidden(_) => ErrorCode::Forbidden,
+            AppError::PreconditionFailed(_) => ErrorCode::PreconditionFailed,
+            AppError::PayloadTooLarge(_) => ErrorCode::PayloadTooLarge,
+            AppError::UnsupportedMediaType(_) => ErrorCode::UnsupportedMediaType,
+            AppError::RateLimited { .. } => ErrorCode::RateLimited,
+            AppError::Validation(_) => ErrorCode::ValidationFailed,
+            AppError::InvalidJson(_) => ErrorCode::InvalidJson,
+            AppError::Internal(_) | AppError::Database(_) => ErrorCode::InternalError,
+        }
+    }
+
+    /// Name of the variant, used as a metrics label.
+    fn variant(&self) -> &'static str {
+        match self {
+            AppError::ProfileNotFound => "ProfileNotFound",
+            AppError::BadRequest(_) => "BadRequest",
+            AppError::Unauthorized(_) => "Unauthorized",
+            AppError::InvalidToken(_) => "InvalidToken",
+            AppError::TokenExpired => "TokenExpired",
+            AppError::Forbidden(_) => "Forbidden",
+            AppError::PreconditionFailed(_) => "PreconditionFailed",
+            AppError::PayloadTooLarge(_) => "PayloadTooLarge",
+            AppError::UnsupportedMediaType(_) => "UnsupportedMediaType",
+            AppError::RateLimited { .. } => "RateLimited",
+            AppError::Validation(_) => "Validation",
+            AppError::InvalidJson(_) => "InvalidJson",
+            AppError::Internal(_) => "Internal",
+            AppError::Database(_) => "Database",
+        }
+    }
+
+    /// Human-readable message sent to the client.
+    fn message(&self) -> String {
+        match self {
+            // Auth messages may quote parts of the credentials.
+            AppError::Unauthorized(msg) => redact(msg).into_owned(),
+            AppError::InvalidToken(msg) => format!("invalid token: {}", redact(msg)),
+            AppError::BadRequest(msg)
+            | AppError::Forbidden(msg)
+            | AppError::PreconditionFailed(msg)
+            | AppError::PayloadTooLarge(msg)
+            | AppError::UnsupportedMediaType(msg)
+            | AppError::Internal(msg) => msg.clone(),
+            AppError::ProfileNotFound | AppError::TokenExpired => self.to_string(),
+            AppError::RateLimited { retry_after } => {
+                format!("too many requests, retry in {retry_after}s")
+            }
+            AppError::Validation(_) => "validation failed".to_string(),
+            AppError::InvalidJson(_) => "invalid JSON body".to_string(),
             AppError::Database(err) => {
                 tracing::error!("Database error: {:?}", err);
-                (
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    "internal server error".to_string(),
-                )
+                "internal server error".to_string()
+            }
+        }
+    }
+
+    /// Field-level breakdown of the error, empty for variants without one.
+    fn details(&self) -> Vec<ErrorDetail> {
+        match self {
+            AppError::Validation(errors) => {
+                let mut details = Vec::new();
+                collect_validation_details(errors, "", &mut details);
+                // `ValidationErrors` is a hash map; keep the output stable.
+                details.sort_by(|a, b| a.field.cmp(&b.field));
+                details
             }
+            AppError::InvalidJson(error) => vec![json_error_detail(error)],
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// Flattens nested struct and list errors into dotted paths such as
+/// `address.city` or `links[1].url`.
+fn collect_validation_details(errors: &ValidationErrors, path: &str, out: &mut Vec<ErrorDetail>) {
+    for (name, kind) in errors.errors() {
+        // Struct-level (`#[validate(schema(...))]`) errors are keyed `__all__`.
+        let field = if name == "__all__" {
+            path.to_string()
+        } else {
+            join_path(path, name)
         };
 
-        let body = json!({
-            "error": {
-                "status": status.as_u16(),
-                "message": message,
+        match kind {
+            ValidationErrorsKind::Field(errors) => {
+                out.extend(errors.iter().map(|e| validation_detail(&field, e)));
+            }
+            ValidationErrorsKind::Struct(nested) => {
+                collect_validation_details(nested, &field, out);
+            }
+            ValidationErrorsKind::List(items) => {
+                for (index, nested) in items {
+                    collect_validation_details(nested, &format!("{field}[{index}]"), out);
+                }
+            }
+        }
+    }
+}
+
+fn validation_detail(field: &str, error: &ValidationError) -> ErrorDetail {
+    // The rejected input is left out: the client sent it, and echoing it back
+    // only spreads it further (e.g. into proxy logs).
+    let params: BTreeMap<String, Value> = error
+        .params
+        .iter()
+        .filter(|(name, _)| *name != "value")
+        .map(|(name, value)| (name.to_string(), value.clone()))
+        .collect();
+
+    let message = match &error.message {
+        Some(message) => message.to_string(),
+        None => default_message(&error.code, &params),
+    };
+
+    ErrorDetail {
+        field: field.to_string(),
+        code: error.code.to_string(),
+        message,
+        params,
+    }
+}
+
+/// Readable fallback for rules declared without a custom `message`.
+fn default_message(code: &str, params: &BTreeMap<String, Value>) -> String {
+    let bounds = || match (params.get("min"), params.get("max")) {
+        (Some(min), Some(max)) => format!("between {min} and {max}"),
+        (Some(min), None) => format!("at least {min}"),
+        (None, Some(max)) => format!("at most {max}"),
+        (None, None) => "out of range".to_string(),
+    };
+
+    match code {
+        "length" => match params.get("equal") {
+            Some(equal) => format!("length must be exactly {equal}"),
+            None => format!("length must be {}", bounds()),
+        },
+        "range" => format!("must be {}", bounds()),
+        "email" => "must be a valid email address".to_string(),
+        "url" => "must be a valid URL".to_string(),
+        "required" => "is required".to_string(),
+        _ => format!("failed {code} validation"),
+    }
+}
+
+fn json_error_detail(error: &JsonError) -> ErrorDetail {
+    let inner = error.inner();
+    let mut field = match error.path().to_string() {
+        path if path == "." => String::new(),
+        path => path,
+    };
+
+    // serde_json appends the position; it is reported as params instead.
+    let full = inner.to_string();
+    let message = full
+        .strip_suffix(&format!(
+            " at line {} column {}",
+            inner.line(),
+            inner.column()
+        ))
+        .unwrap_or(&full)
+        .to_string();
+
+    let mut params = BTreeMap::new();
+
+    let code = if inner.is_data() {
+        const CODES: [(&str, &str); 6] = [
+            ("invalid type", "invalid_type"),
+            ("invalid value", "invalid_value"),
+            ("invalid length", "invalid_length"),
+            ("missing field", "missing_field"),
+            ("unknown field", "unknown_field"),
+            ("unknown variant", "unknown_variant"),
+        ];
+        let code = CODES
+            .iter()
+            .find(|(prefix, _)| message.starts_with(prefix))
+            .map_or("invalid", |(_, code)| code);
+
+        // Serde reports missing and unknown members at their parent; point at
+        // the member itself.
+        if matches!(code, "missing_field" | "unknown_field")
+            && let Some(name) = message.split('`').nth(1)
+        {
+            field = join_path(&field, name);
+        }
+        if let Some((_, expected)) = message.split_once(", expected ") {
+            params.insert("expected".to_string(), Value::from(expected));
+        }
+        code
+    } else {
+        params.insert("line".to_string(), Value::from(inner.line()));
+        params.insert("column".to_string(), Value::from(inner.column()));
+        "malformed_json"
+    };
+
+    ErrorDetail {
+        field,
+        code: code.to_string(),
+        message,
+        params,
+    }
+}
+
+fn join_path(parent: &str, name: &str) -> String {
+    if parent.is_empty() {
+        name.to_string()
+    } else {
+        format!("{parent}.{name}")
+    }
+}
+
+/// axum's built-in rejections answer in plain text. Mapping them onto
+/// `AppError` gives them the usual error body, code and request id.
+macro_rules! from_rejection {
+    ($($rejection:ty),+) => {$(
+        impl From<$rejection> for AppError {
+            fn from(rejection: $rejection) -> Self {
+                let message = rejection.body_text();
+                match rejection.status() {
+                    StatusCode::PAYLOAD_TOO_LARGE => AppError::PayloadTooLarge(message),
+                    StatusCode::UNSUPPORTED_MEDIA_TYPE => AppError::UnsupportedMediaType(message),
+                    status if status.is_server_error() => AppError::Internal(message),
+                    _ => AppError::BadRequest(message),
+                }
             }
-        });
+        }
+    )+};
+}
+
+from_rejection!(
+    BytesRejection,
+    MultipartRejection,
+    PathRejection,
+    QueryRejection
+);
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        metrics::counter!("app_errors_total", "variant" => self.variant()).increment(1);
+
+        let status = self.status();
+        let body = ErrorBody {
+            status: status.as_u16(),
+            code: self.code(),
+            message: self.message(),
+            details: self.details(),
+            request_id: None,
+        };
+
+        // The body is also attached as an extension so `render_errors` can
+        // re-render it with the request id and path.
+        let mut response = (
+            status,
+            Json(ErrorResponse {
+                error: body.clone(),
+            }),
+        )
+            .into_response();
+        if let AppError::RateLimited { retry_after } = self {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from(retry_after));
+        }
+        response.extensions_mut().insert(body);
+        response
+    }
+}
+
+/// Middleware that finishes `AppError` responses with request context: adds
+/// the request id, and renders them as `application/problem+json` (RFC 7807)
+/// when `server.problem_json_errors` is enabled. Other responses pass through
+/// untouched. Must run inside `SetRequestIdLayer`.
+pub async fn render_errors(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    let instance = req.uri().path().to_string();
+    let request_id = req
+        .extensions()
+        .get::<RequestId>()
+        .and_then(|id| id.header_value().to_str().ok())
+        .map(str::to_owned);
+
+    let mut response = next.run(req).await;
+
+    let Some(mut error) = response.extensions_mut().remove::<ErrorBody>() else {
+        return response;
+    };
+    error.request_id = request_id;
+
+    let (mut parts, _) = response.into_parts();
+    let body = if state.config.server.problem_json_errors {
+        parts.headers.insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static(PROBLEM_JSON_CONTENT_TYPE),
+        );
+        serde_json::to_vec(&ProblemDetails::new(error, instance))
+    } else {
+        serde_json::to_vec(&ErrorResponse { error })
+    }
+    .expect("error bodies always serialize");
+    parts.headers.remove(header::CONTENT_LENGTH);
+
+    Response::from_parts(parts, body.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::extractors::validated_json;
+
+    #[test]
+    fn codes_display_as_serialized() {
+        for (code, expected) in [
+            (ErrorCode::BadRequest, "bad_request"),
+            (ErrorCode::ProfileNotFound, "profile_not_found"),
+            (ErrorCode::UnsupportedMediaType, "unsupported_media_type"),
+        ] {
+            assert_eq!(code.to_string(), expected);
+            assert_eq!(serde_json::to_value(code).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn problem_type_is_derived_from_the_code() {
+        let error = AppError::PreconditionFailed("stale".into());
+        let body = ErrorBody {
+            status: error.status().as_u16(),
+            code: error.code(),
+            message: error.message(),
+            details: error.details(),
+            request_id: None,
+        };
+        let problem = ProblemDetails::new(body, "/users/me".into());
+
+        assert_eq!(problem.problem_type, "/problems/precondition_failed");
+        assert_eq!(problem.title, "Precondition failed");
+        assert_eq!(problem.status, 412);
+    }
+
+    #[test]
+    fn trailing_data_is_malformed_json() {
+        let error: AppError = validated_json::from_slice::<Value>(b"{} []")
+            .unwrap_err()
+            .into();
+        let details = error.details();
 
-        (status, Json(body)).into_response()
+        assert_eq!(error.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(details.len(), 1);
+        assert_eq!(details[0].code, "malformed_json");
+        assert_eq!(details[0].message, "trailing characters");
+        assert_eq!(details[0].params["column"], 4);
     }
 }
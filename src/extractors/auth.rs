@@ -1,10 +1,16 @@
+use std::sync::Arc;
+
 use axum::extract::FromRequestParts;
+use axum::http::header::AUTHORIZATION;
 use axum::http::request::Parts;
-use jsonwebtoken::{Algorithm, DecodingKey, Validation, decode};
+use jsonwebtoken::errors::ErrorKind;
+use jsonwebtoken::{Algorithm, DecodingKey, Validation, decode, decode_header};
 use serde::Deserialize;
 
 use crate::AppState;
+use crate::config::AuthConfig;
 use crate::errors::AppError;
+use crate::services::jwks::JwksCache;
 
 /// Claims embedded in a Supabase-issued JWT.
 #[derive(Debug, Deserialize)]
@@ -12,18 +18,77 @@ struct SupabaseClaims {
     sub: String,
     email: Option<String>,
     role: Option<String>,
+    #[serde(default)]
+    app_metadata: AppMetadata,
+}
+
+/// Custom claims set through Supabase's `app_metadata`, which only the service
+/// role can write. Unknown keys are ignored.
+#[derive(Debug, Default, Deserialize)]
+struct AppMetadata {
+    #[serde(default)]
+    roles: Vec<String>,
+    #[serde(default)]
+    permissions: Vec<String>,
 }
 
 /// Extractor that validates the Supabase JWT from the Authorization header
 /// and makes the authenticated user's identity available to handlers.
+///
+/// Asymmetric tokens (RS256/ES256) are verified with the key matching their
+/// `kid` in the project's JWKS; HS256 tokens are only accepted when
+/// `auth.jwt_secret` is configured.
 #[derive(Debug, Clone)]
 pub struct AuthUser {
     pub id: String,
     pub email: String,
-    #[allow(dead_code)]
     pub role: String,
+    pub roles: Vec<String>,
+    pub permissions: Vec<String>,
+}
+
+impl AuthUser {
+    /// True if `role` is the token's Supabase role or one of the custom
+    /// roles from `app_metadata.roles`.
+    pub fn has_role(&self, role: &str) -> bool {
+        self.role == role || self.roles.iter().any(|r| r == role)
+    }
+
+    /// True if `permission` is listed in `app_metadata.permissions`.
+    pub fn has_permission(&self, permission: &str) -> bool {
+        self.permissions.iter().any(|p| p == permission)
+    }
+
+    /// Verifies the request's bearer token, if there is one. Anonymous
+    /// requests and invalid tokens give `None`; rejecting them is left to the
+    /// extractor, which reuses the outcome instead of verifying again.
+    pub async fn identify(parts: &mut Parts, state: &AppState) -> Option<AuthUser> {
+        if !parts.headers.contains_key(AUTHORIZATION) {
+            return None;
+        }
+
+        authenticate(parts, &state.config.auth, &state.jwks)
+            .await
+            .ok()
+    }
+}
+
+#[cfg(test)]
+impl AuthUser {
+    /// Records this user as the verified caller of a request, so extractor
+    /// tests need neither a token nor a JWKS.
+    pub fn verified(self, extensions: &mut axum::http::Extensions) {
+        extensions.insert(Verified(Ok(self)));
+    }
 }
 
+/// Outcome of verifying a request's token, accepted or not, cached in the
+/// request extensions so the token is verified once per request. A second
+/// verification of a token with an unknown `kid` would also mean a second
+/// JWKS refresh attempt.
+#[derive(Clone)]
+struct Verified(Result<AuthUser, Arc<AppError>>);
+
 impl FromRequestParts<AppState> for AuthUser {
     type Rejection = AppError;
 
@@ -31,32 +96,382 @@ impl FromRequestParts<AppState> for AuthUser {
         parts: &mut Parts,
         state: &AppState,
     ) -> Result<Self, Self::Rejection> {
-        let header = parts
-            .headers
-            .get("Authorization")
-            .and_then(|v| v.to_str().ok())
-            .ok_or_else(|| AppError::Unauthorized("missing authorization header".into()))?;
+        let user = authenticate(parts, &state.config.auth, &state.jwks)
+            .await
+            .inspect_err(|e| {
+                metrics::counter!("auth_rejections_total", "reason" => e.code().to_string())
+                    .increment(1);
+            })?;
+
+        // Recorded here rather than in `authenticate`, whose own span would
+        // otherwise be the current one.
+        tracing::Span::current().record("user_id", user.id.as_str());
+        Ok(user)
+    }
+}
+
+/// Verifies the request's bearer token, or returns the outcome of an earlier
+/// verification of the same request.
+async fn authenticate(
+    parts: &mut Parts,
+    config: &AuthConfig,
+    jwks: &JwksCache,
+) -> Result<AuthUser, AppError> {
+    if let Some(Verified(outcome)) = parts.extensions.get::<Verified>() {
+        return outcome.clone().map_err(|e| rejection(&e));
+    }
+
+    let outcome = verify_bearer(parts, config, jwks).await.map_err(Arc::new);
+    parts.extensions.insert(Verified(outcome.clone()));
+    outcome.map_err(|e| rejection(&e))
+}
+
+/// Rebuilds a cached rejection. Verification only fails with these variants,
+/// which hold nothing but a message.
+fn rejection(error: &AppError) -> AppError {
+    match error {
+        AppError::Unauthorized(msg) => AppError::Unauthorized(msg.clone()),
+        AppError::InvalidToken(msg) => AppError::InvalidToken(msg.clone()),
+        AppError::TokenExpired => AppError::TokenExpired,
+        AppError::Internal(msg) => AppError::Internal(msg.clone()),
+        other => AppError::Internal(other.to_string()),
+    }
+}
+
+/// Takes the bearer token from the request and verifies it.
+#[tracing::instrument(skip_all)]
+async fn verify_bearer(
+    parts: &Parts,
+    config: &AuthConfig,
+    jwks: &JwksCache,
+) -> Result<AuthUser, AppError> {
+    let header = parts
+        .headers
+        .get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .ok_or_else(|| AppError::Unauthorized("missing authorization header".into()))?;
+
+    let token = header
+        .strip_prefix("Bearer ")
+        .ok_or_else(|| AppError::Unauthorized("invalid authorization format".into()))?;
+
+    verify(token, config, jwks).await
+}
+
+/// Verifies the token's signature, expiry, audience and issuer and builds the
+/// caller's identity from its claims.
+async fn verify(token: &str, config: &AuthConfig, jwks: &JwksCache) -> Result<AuthUser, AppError> {
+    let token_header = decode_header(token).map_err(token_error)?;
+
+    let (key, algorithm) = match token_header.alg {
+        Algorithm::HS256 => {
+            let secret = config.jwt_secret.as_ref().ok_or_else(|| {
+                AppError::InvalidToken("symmetric tokens are not accepted".into())
+            })?;
+            (
+                DecodingKey::from_secret(secret.as_bytes()),
+                Algorithm::HS256,
+            )
+        }
+        alg => {
+            let kid = token_header
+                .kid
+                .ok_or_else(|| AppError::InvalidToken("token is missing a key id".into()))?;
+            let signing_key = jwks.key(&kid).await?;
+
+            if signing_key.algorithm != alg {
+                return Err(AppError::InvalidToken(
+                    "token algorithm does not match signing key".into(),
+                ));
+            }
+            (signing_key.key, signing_key.algorithm)
+        }
+    };
+
+    let mut validation = Validation::new(algorithm);
+    validation.set_audience(&["authenticated"]);
+    validation.set_issuer(&[config.issuer()]);
+    validation.set_required_spec_claims(&["exp", "aud", "iss"]);
+
+    let token_data = decode::<SupabaseClaims>(token, &key, &validation).map_err(token_error)?;
+
+    let claims = token_data.claims;
+
+    Ok(AuthUser {
+        id: claims.sub,
+        email: claims.email.unwrap_or_default(),
+        role: claims.role.unwrap_or_else(|| "authenticated".into()),
+        roles: claims.app_metadata.roles,
+        permissions: claims.app_metadata.permissions,
+    })
+}
+
+/// Expired tokens get their own code so clients know to refresh rather than
+/// sign in again.
+fn token_error(e: jsonwebtoken::errors::Error) -> AppError {
+    match e.kind() {
+        ErrorKind::ExpiredSignature => AppError::TokenExpired,
+        _ => AppError::InvalidToken(e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    use axum::Json;
+    use axum::routing::get;
+    use base64::Engine;
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use jsonwebtoken::{EncodingKey, Header, encode};
+    use ring::rand::SystemRandom;
+    use ring::signature::{ECDSA_P256_SHA256_FIXED_SIGNING, EcdsaKeyPair, KeyPair};
+    use serde_json::{Value, json};
+
+    use super::*;
+
+    /// A P-256 key generated for the test run, published in a JWKS served
+    /// from a local listener the way Supabase publishes its keys.
+    struct Issuer {
+        config: AuthConfig,
+        jwks: JwksCache,
+        signing_key: EncodingKey,
+    }
+
+    impl Issuer {
+        /// Publishes the key as `k1` for ES256 and again as `k2` advertised
+        /// for ES384, so tokens can claim an algorithm the key is not for.
+        async fn start() -> Self {
+            let rng = SystemRandom::new();
+            let pkcs8 =
+                EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng).unwrap();
+            let pair =
+                EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref(), &rng)
+                    .unwrap();
 
-        let token = header
-            .strip_prefix("Bearer ")
-            .ok_or_else(|| AppError::Unauthorized("invalid authorization format".into()))?;
+            // Uncompressed point: 0x04, then x and y.
+            let point = pair.public_key().as_ref();
+            let jwk = |kid: &str, alg: &str| {
+                json!({
+                    "kty": "EC", "crv": "P-256", "use": "sig", "kid": kid, "alg": alg,
+                    "x": URL_SAFE_NO_PAD.encode(&point[1..33]),
+                    "y": URL_SAFE_NO_PAD.encode(&point[33..]),
+                })
+            };
+            let document = json!({ "keys": [jwk("k1", "ES256"), jwk("k2", "ES384")] });
 
-        let mut validation = Validation::new(Algorithm::HS256);
-        validation.set_audience(&["authenticated"]);
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let app = axum::Router::new().route(
+                "/auth/v1/.well-known/jwks.json",
+                get(move || async move { Json(document) }),
+            );
+            tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
 
-        let token_data = decode::<SupabaseClaims>(
-            token,
-            &DecodingKey::from_secret(state.config.supabase_jwt_secret.as_bytes()),
-            &validation,
+            let config = AuthConfig {
+                supabase_url: format!("http://{addr}"),
+                jwt_secret: None,
+                jwks_ttl_secs: 600,
+            };
+
+            Issuer {
+                jwks: JwksCache::new(&config),
+                config,
+                signing_key: EncodingKey::from_ec_der(pkcs8.as_ref()),
+            }
+        }
+
+        fn claims(&self) -> Value {
+            json!({
+                "sub": "user-1",
+                "email": "user@example.com",
+                "aud": "authenticated",
+                "iss": self.config.issuer(),
+                "exp": now() + 3600,
+                "app_metadata": { "roles": ["admin"], "permissions": ["profiles:delete"] },
+            })
+        }
+
+        fn sign(&self, alg: Algorithm, kid: &str, claims: &Value) -> String {
+            let mut header = Header::new(alg);
+            header.kid = Some(kid.into());
+            encode(&header, claims, &self.signing_key).unwrap()
+        }
+
+        async fn verify(&self, token: &str) -> Result<AuthUser, AppError> {
+            verify(token, &self.config, &self.jwks).await
+        }
+    }
+
+    fn now() -> i64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64
+    }
+
+    fn assert_invalid(result: Result<AuthUser, AppError>, expected: &str) {
+        match result {
+            Err(AppError::InvalidToken(message)) => assert!(
+                message.contains(expected),
+                "expected {expected:?}, got {message:?}"
+            ),
+            Err(other) => panic!("expected an invalid token, got {other:?}"),
+            Ok(user) => panic!("expected an invalid token, got {user:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn accepts_token_signed_with_published_key() {
+        let issuer = Issuer::start().await;
+        let token = issuer.sign(Algorithm::ES256, "k1", &issuer.claims());
+
+        let user = issuer.verify(&token).await.unwrap();
+
+        assert_eq!(user.id, "user-1");
+        assert_eq!(user.email, "user@example.com");
+        assert_eq!(user.role, "authenticated");
+        assert!(user.has_role("admin"));
+        assert!(user.has_permission("profiles:delete"));
+        assert!(!user.has_permission("admin"));
+    }
+
+    #[tokio::test]
+    async fn rejects_unknown_kid() {
+        let issuer = Issuer::start().await;
+        let token = issuer.sign(Algorithm::ES256, "rotated-away", &issuer.claims());
+
+        assert_invalid(issuer.verify(&token).await, "unknown signing key");
+    }
+
+    #[tokio::test]
+    async fn rejects_token_without_kid() {
+        let issuer = Issuer::start().await;
+        let token = encode(
+            &Header::new(Algorithm::ES256),
+            &issuer.claims(),
+            &issuer.signing_key,
+        )
+        .unwrap();
+
+        assert_invalid(issuer.verify(&token).await, "missing a key id");
+    }
+
+    #[tokio::test]
+    async fn rejects_algorithm_the_key_is_not_published_for() {
+        let issuer = Issuer::start().await;
+        let token = issuer.sign(Algorithm::ES256, "k2", &issuer.claims());
+
+        assert_invalid(issuer.verify(&token).await, "does not match signing key");
+    }
+
+    #[tokio::test]
+    async fn rejects_symmetric_token_without_configured_secret() {
+        let issuer = Issuer::start().await;
+        let token = encode(
+            &Header::new(Algorithm::HS256),
+            &issuer.claims(),
+            &EncodingKey::from_secret(b"guessed"),
+        )
+        .unwrap();
+
+        assert_invalid(
+            issuer.verify(&token).await,
+            "symmetric tokens are not accepted",
+        );
+    }
+
+    #[tokio::test]
+    async fn accepts_symmetric_token_with_configured_secret() {
+        let mut issuer = Issuer::start().await;
+        issuer.config.jwt_secret = Some("legacy-secret".into());
+        let token = encode(
+            &Header::new(Algorithm::HS256),
+            &issuer.claims(),
+            &EncodingKey::from_secret(b"legacy-secret"),
         )
-        .map_err(|e| AppError::Unauthorized(format!("invalid token: {e}")))?;
+        .unwrap();
+
+        assert_eq!(issuer.verify(&token).await.unwrap().id, "user-1");
+    }
+
+    #[tokio::test]
+    async fn rejects_expired_token() {
+        let issuer = Issuer::start().await;
+        let mut claims = issuer.claims();
+        // Past the default 60 second leeway.
+        claims["exp"] = json!(now() - 120);
+        let token = issuer.sign(Algorithm::ES256, "k1", &claims);
+
+        assert!(matches!(
+            issuer.verify(&token).await,
+            Err(AppError::TokenExpired)
+        ));
+    }
+
+    #[tokio::test]
+    async fn rejects_token_from_another_issuer() {
+        let issuer = Issuer::start().await;
+        let mut claims = issuer.claims();
+        claims["iss"] = json!("https://other-project.supabase.co/auth/v1");
+        let token = issuer.sign(Algorithm::ES256, "k1", &claims);
 
-        let claims = token_data.claims;
+        assert_invalid(issuer.verify(&token).await, "InvalidIssuer");
+    }
+
+    #[tokio::test]
+    async fn rejects_token_without_issuer() {
+        let issuer = Issuer::start().await;
+        let mut claims = issuer.claims();
+        claims.as_object_mut().unwrap().remove("iss");
+        let token = issuer.sign(Algorithm::ES256, "k1", &claims);
+
+        assert_invalid(issuer.verify(&token).await, "iss");
+    }
+
+    #[tokio::test]
+    async fn rejects_token_for_another_audience() {
+        let issuer = Issuer::start().await;
+        let mut claims = issuer.claims();
+        claims["aud"] = json!("anon");
+        let token = issuer.sign(Algorithm::ES256, "k1", &claims);
+
+        assert_invalid(issuer.verify(&token).await, "InvalidAudience");
+    }
+
+    fn request_parts(token: &str) -> Parts {
+        let req = axum::http::Request::builder()
+            .header(AUTHORIZATION, format!("Bearer {token}"))
+            .body(())
+            .unwrap();
+        req.into_parts().0
+    }
+
+    #[tokio::test]
+    async fn verifies_each_request_once() {
+        let issuer = Issuer::start().await;
+        let valid = issuer.sign(Algorithm::ES256, "k1", &issuer.claims());
+        let unknown_kid = issuer.sign(Algorithm::ES256, "rotated-away", &issuer.claims());
+
+        // A rejection is cached too: swapping in a valid token afterwards
+        // does not trigger a second verification.
+        let mut parts = request_parts(&unknown_kid);
+        let first = authenticate(&mut parts, &issuer.config, &issuer.jwks).await;
+        assert_invalid(first, "unknown signing key");
+        parts
+            .headers
+            .insert(AUTHORIZATION, format!("Bearer {valid}").parse().unwrap());
+        let second = authenticate(&mut parts, &issuer.config, &issuer.jwks).await;
+        assert_invalid(second, "unknown signing key");
 
-        Ok(AuthUser {
-            id: claims.sub,
-            email: claims.email.unwrap_or_default(),
-            role: claims.role.unwrap_or_else(|| "authenticated".into()),
-        })
+        let mut parts = request_parts(&valid);
+        let user = authenticate(&mut parts, &issuer.config, &issuer.jwks).await;
+        assert_eq!(user.unwrap().id, "user-1");
+        parts.headers.insert(
+            AUTHORIZATION,
+            format!("Bearer {unknown_kid}").parse().unwrap(),
+        );
+        let user = authenticate(&mut parts, &issuer.config, &issuer.jwks).await;
+        assert_eq!(user.unwrap().id, "user-1");
     }
 }
@@ -0,0 +1,201 @@
+use std::marker::PhantomData;
+use std::ops::Deref;
+
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+
+use crate::AppState;
+use crate::errors::AppError;
+use crate::extractors::auth::AuthUser;
+
+/// A role a handler can require through `RequireRole`. Implemented by
+/// zero-sized marker types so the requirement is visible in the signature.
+pub trait Role: Send + Sync + 'static {
+    const NAME: &'static str;
+}
+
+/// A permission a handler can require through `RequirePermission`, matched
+/// against `app_metadata.permissions`.
+pub trait Permission: Send + Sync + 'static {
+    const NAME: &'static str;
+}
+
+/// Operators allowed to manage other users' data.
+pub struct Admin;
+
+impl Role for Admin {
+    const NAME: &'static str = "admin";
+}
+
+/// Deleting other users' profiles. Admins need it on top of their role, so
+/// not every operator can remove accounts.
+pub struct DeleteProfiles;
+
+impl Permission for DeleteProfiles {
+    const NAME: &'static str = "profiles:delete";
+}
+
+/// Extractor that authenticates the caller like `AuthUser` and rejects the
+/// request with 403 unless they hold role `R`. Can also guard a whole router
+/// via `axum::middleware::from_extractor_with_state::<RequireRole<R>, _>`.
+pub struct RequireRole<R: Role> {
+    pub user: AuthUser,
+    role: PhantomData<R>,
+}
+
+impl<R: Role> Deref for RequireRole<R> {
+    type Target = AuthUser;
+
+    fn deref(&self) -> &AuthUser {
+        &self.user
+    }
+}
+
+impl<R: Role> FromRequestParts<AppState> for RequireRole<R> {
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let user = AuthUser::from_request_parts(parts, state).await?;
+
+        if !user.has_role(R::NAME) {
+            return Err(AppError::Forbidden(format!(
+                "missing required role: {}",
+                R::NAME
+            )));
+        }
+
+        Ok(RequireRole {
+            user,
+            role: PhantomData,
+        })
+    }
+}
+
+/// Extractor that authenticates the caller like `AuthUser` and rejects the
+/// request with 403 unless they hold permission `P`.
+pub struct RequirePermission<P: Permission> {
+    pub user: AuthUser,
+    permission: PhantomData<P>,
+}
+
+impl<P: Permission> Deref for RequirePermission<P> {
+    type Target = AuthUser;
+
+    fn deref(&self) -> &AuthUser {
+        &self.user
+    }
+}
+
+impl<P: Permission> FromRequestParts<AppState> for RequirePermission<P> {
+    type Rejection = AppError;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let user = AuthUser::from_request_parts(parts, state).await?;
+
+        if !user.has_permission(P::NAME) {
+            return Err(AppError::Forbidden(format!(
+                "missing required permission: {}",
+                P::NAME
+            )));
+        }
+
+        Ok(RequirePermission {
+            user,
+            permission: PhantomData,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::Router;
+    use axum::body::{Body, to_bytes};
+    use axum::http::{Request, StatusCode};
+    use axum::response::Response;
+    use axum::routing::get;
+    use serde_json::Value;
+    use tower::ServiceExt;
+
+    use super::*;
+
+    fn user(roles: &[&str], permissions: &[&str]) -> AuthUser {
+        AuthUser {
+            id: "user-1".into(),
+            email: "user@example.com".into(),
+            role: "authenticated".into(),
+            roles: roles.iter().map(|r| r.to_string()).collect(),
+            permissions: permissions.iter().map(|p| p.to_string()).collect(),
+        }
+    }
+
+    async fn call(app: Router<AppState>, caller: AuthUser) -> Response {
+        let mut req = Request::builder().uri("/").body(Body::empty()).unwrap();
+        caller.verified(req.extensions_mut());
+        app.with_state(AppState::for_tests())
+            .oneshot(req)
+            .await
+            .unwrap()
+    }
+
+    async fn error_code(res: Response) -> Value {
+        let body = to_bytes(res.into_body(), usize::MAX).await.unwrap();
+        let body: Value = serde_json::from_slice(&body).unwrap();
+        body["error"]["code"].clone()
+    }
+
+    fn admin_route() -> Router<AppState> {
+        Router::new().route(
+            "/",
+            get(|admin: RequireRole<Admin>| async move { admin.id.clone() }),
+        )
+    }
+
+    fn delete_route() -> Router<AppState> {
+        Router::new().route(
+            "/",
+            get(|user: RequirePermission<DeleteProfiles>| async move { user.id.clone() }),
+        )
+    }
+
+    #[tokio::test]
+    async fn require_role_admits_holders_of_the_role() {
+        let res = call(admin_route(), user(&["admin"], &[])).await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let mut supabase_admin = user(&[], &[]);
+        supabase_admin.role = "admin".into();
+        let res = call(admin_route(), supabase_admin).await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn require_role_forbids_everyone_else() {
+        for caller in [user(&[], &[]), user(&["editor"], &["admin"])] {
+            let res = call(admin_route(), caller).await;
+            assert_eq!(res.status(), StatusCode::FORBIDDEN);
+            assert_eq!(error_code(res).await, "forbidden");
+        }
+    }
+
+    #[tokio::test]
+    async fn require_permission_checks_app_metadata_permissions() {
+        let res = call(delete_route(), user(&[], &["profiles:delete"])).await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        // Roles do not grant permissions, even one spelled the same.
+        for caller in [
+            user(&["admin"], &[]),
+            user(&["profiles:delete"], &["profiles:read"]),
+        ] {
+            let res = call(delete_route(), caller).await;
+            assert_eq!(res.status(), StatusCode::FORBIDDEN);
+            assert_eq!(error_code(res).await, "forbidden");
+        }
+    }
+}
@@ -0,0 +1,184 @@
+use std::convert::Infallible;
+
+use axum::extract::FromRequestParts;
+use axum::http::header::{self, HeaderMap, HeaderName, HeaderValue};
+use axum::http::request::Parts;
+use axum::response::{IntoResponseParts, ResponseParts};
+
+use crate::errors::AppError;
+use crate::models::profile;
+
+/// Strong entity tag identifying one version of a resource.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ETag(String);
+
+impl ETag {
+    /// Profiles change version whenever `updated_at` moves, which every write
+    /// in `services::user` bumps.
+    pub fn for_profile(profile: &profile::Model) -> Self {
+        ETag(format!("\"{:x}\"", profile.updated_at.timestamp_micros()))
+    }
+}
+
+impl IntoResponseParts for ETag {
+    type Error = Infallible;
+
+    fn into_response_parts(self, mut res: ResponseParts) -> Result<ResponseParts, Self::Error> {
+        if let Ok(value) = HeaderValue::from_str(&self.0) {
+            res.headers_mut().insert(header::ETAG, value);
+        }
+        Ok(res)
+    }
+}
+
+/// The `If-Match` / `If-None-Match` request headers (RFC 9110 §13.1).
+/// Absent headers always pass.
+pub struct Preconditions {
+    if_match: Option<Vec<String>>,
+    if_none_match: Option<Vec<String>>,
+}
+
+impl Preconditions {
+    pub fn has_if_match(&self) -> bool {
+        self.if_match.is_some()
+    }
+
+    /// Fails with 412 unless `If-Match` is `*` or lists `current`. Uses strong
+    /// comparison, so weak tags never match. `None` means the resource does
+    /// not exist, which no `If-Match`, not even `*`, matches (RFC 9110
+    /// §13.1.1).
+    pub fn check_if_match(&self, current: Option<&ETag>) -> Result<(), AppError> {
+        let Some(tags) = &self.if_match else {
+            return Ok(());
+        };
+        let Some(current) = current else {
+            return Err(AppError::PreconditionFailed(
+                "resource does not exist".into(),
+            ));
+        };
+
+        if tags.iter().any(|tag| tag == "*" || *tag == current.0) {
+            Ok(())
+        } else {
+            Err(AppError::PreconditionFailed(
+                "resource has been modified".into(),
+            ))
+        }
+    }
+
+    /// True when `If-None-Match` is `*` or lists `current`, meaning the client
+    /// already holds this version and a read can answer `304 Not Modified`.
+    /// Uses weak comparison.
+    pub fn is_not_modified(&self, current: &ETag) -> bool {
+        self.if_none_match.as_ref().is_some_and(|tags| {
+            tags.iter()
+                .any(|tag| tag == "*" || tag.trim_start_matches("W/") == current.0)
+        })
+    }
+}
+
+/// Collects the comma-separated entity tags of every occurrence of `name`.
+fn entity_tags(headers: &HeaderMap, name: HeaderName) -> Result<Option<Vec<String>>, AppError> {
+    let mut tags = Vec::new();
+    let mut present = false;
+
+    for value in headers.get_all(name.clone()) {
+        present = true;
+        let value = value
+            .to_str()
+            .map_err(|_| AppError::BadRequest(format!("invalid {name} header")))?;
+        tags.extend(
+            value
+                .split(',')
+                .map(str::trim)
+                .filter(|tag| !tag.is_empty())
+                .map(str::to_owned),
+        );
+    }
+
+    Ok(present.then_some(tags))
+}
+
+impl<S> FromRequestParts<S> for Preconditions
+where
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(Preconditions {
+            if_match: entity_tags(&parts.headers, header::IF_MATCH)?,
+            if_none_match: entity_tags(&parts.headers, header::IF_NONE_MATCH)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::Request;
+
+    use super::*;
+
+    async fn preconditions(headers: &[(HeaderName, &str)]) -> Preconditions {
+        let mut request = Request::builder();
+        for (name, value) in headers {
+            request = request.header(name, *value);
+        }
+        let (mut parts, ()) = request.body(()).unwrap().into_parts();
+        Preconditions::from_request_parts(&mut parts, &())
+            .await
+            .unwrap()
+    }
+
+    fn tag(value: &str) -> ETag {
+        ETag(value.into())
+    }
+
+    #[tokio::test]
+    async fn absent_if_match_always_passes() {
+        let none = preconditions(&[]).await;
+
+        assert!(none.check_if_match(Some(&tag("\"1\""))).is_ok());
+        assert!(none.check_if_match(None).is_ok());
+    }
+
+    #[tokio::test]
+    async fn if_match_uses_strong_comparison() {
+        let listed = preconditions(&[(header::IF_MATCH, "\"0\", \"1\"")]).await;
+        let weak = preconditions(&[(header::IF_MATCH, "W/\"1\"")]).await;
+
+        assert!(listed.check_if_match(Some(&tag("\"1\""))).is_ok());
+        assert!(matches!(
+            listed.check_if_match(Some(&tag("\"2\""))),
+            Err(AppError::PreconditionFailed(_))
+        ));
+        assert!(weak.check_if_match(Some(&tag("\"1\""))).is_err());
+    }
+
+    #[tokio::test]
+    async fn missing_resource_fails_any_if_match() {
+        for value in ["*", "\"1\""] {
+            let conditions = preconditions(&[(header::IF_MATCH, value)]).await;
+
+            assert!(
+                matches!(
+                    conditions.check_if_match(None),
+                    Err(AppError::PreconditionFailed(_))
+                ),
+                "{value}"
+            );
+        }
+        let star = preconditions(&[(header::IF_MATCH, "*")]).await;
+        assert!(star.check_if_match(Some(&tag("\"1\""))).is_ok());
+    }
+
+    #[tokio::test]
+    async fn if_none_match_uses_weak_comparison() {
+        let weak = preconditions(&[(header::IF_NONE_MATCH, "W/\"1\"")]).await;
+        let star = preconditions(&[(header::IF_NONE_MATCH, "*")]).await;
+
+        assert!(weak.is_not_modified(&tag("\"1\"")));
+        assert!(!weak.is_not_modified(&tag("\"2\"")));
+        assert!(star.is_not_modified(&tag("\"2\"")));
+    }
+}
@@ -0,0 +1,119 @@
+use axum::body::Bytes;
+use axum::extract::{FromRequest, Request};
+use axum::http::header;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer};
+use validator::Validate;
+
+use crate::errors::AppError;
+use crate::extractors::validated_json;
+
+pub const MERGE_PATCH_CONTENT_TYPE: &str = "application/merge-patch+json";
+
+/// An Axum extractor for JSON Merge Patch documents (RFC 7396). Requires the
+/// `application/merge-patch+json` content type and a JSON object body, then
+/// deserializes and validates it like `ValidatedJson`.
+///
+/// Fields of `T` that can be cleared should be `Option<Option<_>>` annotated
+/// with `#[serde(default, deserialize_with = "nullable")]`: `None` means the
+/// member was absent, `Some(None)` an explicit `null`.
+pub struct MergePatch<T>(pub T);
+
+impl<S, T> FromRequest<S> for MergePatch<T>
+where
+    T: DeserializeOwned + Validate,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let content_type = req
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.split(';').next())
+            .map(str::trim);
+
+        if content_type != Some(MERGE_PATCH_CONTENT_TYPE) {
+            return Err(AppError::UnsupportedMediaType(format!(
+                "expected content type {MERGE_PATCH_CONTENT_TYPE}"
+            )));
+        }
+
+        let body = Bytes::from_request(req, state)
+            .await
+            .map_err(AppError::from)?;
+
+        let document: serde_json::Value = validated_json::from_slice(&body)?;
+
+        // A non-object patch would replace the whole resource, which is never
+        // what a client means for a profile.
+        if !document.is_object() {
+            return Err(AppError::BadRequest(
+                "merge patch document must be a JSON object".into(),
+            ));
+        }
+
+        let value: T = serde_path_to_error::deserialize(document)?;
+
+        value.validate()?;
+
+        Ok(MergePatch(value))
+    }
+}
+
+/// Deserializes a present member into `Some`, keeping an explicit `null` as
+/// `Some(None)`. Combine with `#[serde(default)]` so absent members stay `None`.
+pub fn nullable<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+
+    use super::*;
+
+    #[derive(Debug, Deserialize, Validate)]
+    struct Patch {
+        #[serde(default, deserialize_with = "nullable")]
+        bio: Option<Option<String>>,
+    }
+
+    async fn extract(body: &'static str) -> Result<Patch, AppError> {
+        let req = Request::builder()
+            .header(header::CONTENT_TYPE, MERGE_PATCH_CONTENT_TYPE)
+            .body(Body::from(body))
+            .unwrap();
+        MergePatch::from_request(req, &())
+            .await
+            .map(|patch| patch.0)
+    }
+
+    #[tokio::test]
+    async fn distinguishes_absent_and_null_members() {
+        assert_eq!(extract("{}").await.unwrap().bio, None);
+        assert_eq!(extract("{\"bio\":null}").await.unwrap().bio, Some(None));
+        assert_eq!(
+            extract("{\"bio\":\"hi\"}").await.unwrap().bio,
+            Some(Some("hi".into()))
+        );
+    }
+
+    #[tokio::test]
+    async fn rejects_trailing_data() {
+        match extract("{\"bio\":null} {}").await {
+            Err(AppError::InvalidJson(e)) => assert!(e.inner().is_syntax()),
+            other => panic!("expected invalid JSON, got {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_documents_that_are_not_objects() {
+        assert!(matches!(extract("[]").await, Err(AppError::BadRequest(_))));
+    }
+}
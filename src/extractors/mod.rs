@@ -1,2 +1,9 @@
 pub mod auth;
+pub mod authorization;
+pub mod conditional;
+pub mod merge_patch;
+pub mod multipart;
+pub mod pagination;
+pub mod path;
 pub mod validated_json;
+pub mod validated_query;
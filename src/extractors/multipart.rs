@@ -0,0 +1,44 @@
+use axum::extract::{FromRequest, Request};
+
+use crate::errors::AppError;
+
+/// `axum::extract::Multipart` that rejects with `AppError`, so a request
+/// that is not `multipart/form-data` gets the usual error body instead of
+/// axum's plain-text one.
+pub struct Multipart(pub axum::extract::Multipart);
+
+impl<S> FromRequest<S> for Multipart
+where
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        Ok(Multipart(
+            axum::extract::Multipart::from_request(req, state).await?,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+    use axum::http::{StatusCode, header};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn non_multipart_requests_are_rejected_with_app_error() {
+        let request = Request::post("/")
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from("{}"))
+            .unwrap();
+
+        let Err(rejection) = Multipart::from_request(request, &()).await else {
+            panic!("a JSON body is not multipart");
+        };
+
+        assert!(matches!(rejection, AppError::BadRequest(_)));
+        assert_eq!(rejection.status(), StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,179 @@
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+use validator::Validate;
+
+use crate::errors::AppError;
+use crate::extractors::validated_query::ValidatedQuery;
+
+const DEFAULT_LIMIT: u64 = 20;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    #[default]
+    Asc,
+    Desc,
+}
+
+/// Query parameters read by `Pagination`, exposed so handlers can document them.
+#[derive(Deserialize, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PaginationQuery {
+    /// Page size, 1–100 (default 20).
+    #[validate(range(min = 1, max = 100))]
+    #[param(minimum = 1, maximum = 100)]
+    limit: Option<u64>,
+    /// `next_cursor` from the previous page; omit for the first one.
+    #[validate(length(min = 1, max = 512))]
+    cursor: Option<String>,
+    /// Sort direction (default `asc`).
+    order: Option<SortOrder>,
+}
+
+/// Cursor-based pagination parameters (`?limit=&cursor=&order=`) shared by
+/// every list endpoint. The cursor is opaque to clients: it encodes the sort
+/// key of the last item of the previous page, whatever that key is for the
+/// resource being listed.
+pub struct Pagination {
+    pub limit: u64,
+    pub order: SortOrder,
+    cursor: Option<String>,
+}
+
+impl Pagination {
+    /// Decodes the `cursor` parameter into the resource's sort key.
+    pub fn after<K: DeserializeOwned>(&self) -> Result<Option<K>, AppError> {
+        let Some(cursor) = &self.cursor else {
+            return Ok(None);
+        };
+
+        URL_SAFE_NO_PAD
+            .decode(cursor)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .map(Some)
+            .ok_or_else(|| AppError::BadRequest("invalid cursor".into()))
+    }
+
+    /// Number of rows to fetch: one more than the page size, so `page` can
+    /// tell whether another page follows without a separate count query.
+    pub fn fetch_limit(&self) -> u64 {
+        self.limit + 1
+    }
+
+    /// Builds the response envelope from rows fetched with `fetch_limit`,
+    /// deriving `next_cursor` from the sort key of the last returned row.
+    pub fn page<T, K: Serialize>(&self, mut rows: Vec<T>, key: impl Fn(&T) -> K) -> Page<T> {
+        let has_more = rows.len() as u64 > self.limit;
+        rows.truncate(self.limit as usize);
+
+        let next_cursor = match rows.last() {
+            Some(last) if has_more => Some(encode_cursor(&key(last))),
+            _ => None,
+        };
+
+        Page {
+            data: rows,
+            next_cursor,
+        }
+    }
+}
+
+fn encode_cursor<K: Serialize>(key: &K) -> String {
+    let bytes = serde_json::to_vec(key).expect("cursor keys are always serializable");
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+impl<S> FromRequestParts<S> for Pagination
+where
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let ValidatedQuery(query) =
+            ValidatedQuery::<PaginationQuery>::from_request_parts(parts, state).await?;
+
+        Ok(Pagination {
+            limit: query.limit.unwrap_or(DEFAULT_LIMIT),
+            order: query.order.unwrap_or_default(),
+            cursor: query.cursor,
+        })
+    }
+}
+
+/// Envelope returned by every paginated list endpoint. `next_cursor` is
+/// `null` on the last page.
+#[derive(Serialize, ToSchema)]
+pub struct Page<T> {
+    pub data: Vec<T>,
+    pub next_cursor: Option<String>,
+}
+
+impl<T> Page<T> {
+    pub fn map<U>(self, f: impl FnMut(T) -> U) -> Page<U> {
+        Page {
+            data: self.data.into_iter().map(f).collect(),
+            next_cursor: self.next_cursor,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+
+    fn pagination(cursor: Option<String>) -> Pagination {
+        Pagination {
+            limit: 2,
+            order: SortOrder::Asc,
+            cursor,
+        }
+    }
+
+    #[test]
+    fn next_cursor_decodes_to_the_last_row_key() {
+        let rows = vec![(1, "a"), (2, "b"), (3, "c")];
+        let page = pagination(None).page(rows, |row| row.0);
+
+        assert_eq!(page.data, vec![(1, "a"), (2, "b")]);
+        let after: Option<i32> = pagination(page.next_cursor).after().unwrap();
+        assert_eq!(after, Some(2));
+    }
+
+    #[test]
+    fn last_page_has_no_cursor() {
+        let page = pagination(None).page(vec![1, 2], |row| *row);
+
+        assert_eq!(page.next_cursor, None);
+    }
+
+    #[test]
+    fn rejects_malformed_cursor() {
+        for cursor in ["not base64!", "bm90IGpzb24"] {
+            let result = pagination(Some(cursor.into())).after::<(u32, Uuid)>();
+            assert!(matches!(result, Err(AppError::BadRequest(_))), "{cursor}");
+        }
+    }
+
+    #[test]
+    fn search_rank_survives_the_cursor_exactly() {
+        let id = Uuid::new_v4();
+        let ranks = [0.060_792_7_f32, 0.1, 1e-20, f32::MIN_POSITIVE, 0.999_999_94];
+        for base in ranks {
+            // Neighbouring values catch anything that rounds to the nearest decimal.
+            for rank in [base, f32::from_bits(base.to_bits() + 1)] {
+                let cursor = encode_cursor(&(rank.to_bits(), id));
+                let after: Option<(u32, Uuid)> = pagination(Some(cursor)).after().unwrap();
+                assert_eq!(after.map(|(bits, _)| f32::from_bits(bits)), Some(rank));
+            }
+        }
+    }
+}
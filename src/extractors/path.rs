@@ -0,0 +1,70 @@
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+use serde::de::DeserializeOwned;
+
+use crate::errors::AppError;
+
+/// `axum::extract::Path` that rejects with `AppError`, so a malformed path
+/// parameter such as a bad UUID gets the usual `400` error body instead of
+/// axum's plain-text one.
+pub struct Path<T>(pub T);
+
+impl<S, T> FromRequestParts<S> for Path<T>
+where
+    T: DeserializeOwned + Send,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let axum::extract::Path(value) =
+            axum::extract::Path::<T>::from_request_parts(parts, state).await?;
+
+        Ok(Path(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::{Body, to_bytes};
+    use axum::http::{Request, StatusCode};
+    use axum::routing::get;
+    use axum::{Router, middleware};
+    use serde_json::Value;
+    use tower::ServiceExt;
+    use tower_http::request_id::{MakeRequestUuid, SetRequestIdLayer};
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::AppState;
+    use crate::errors::render_errors;
+
+    #[tokio::test]
+    async fn malformed_parameters_get_the_error_body() {
+        let state = AppState::for_tests();
+        let app = Router::new()
+            .route(
+                "/users/{id}",
+                get(async |Path(id): Path<Uuid>| id.to_string()),
+            )
+            .layer(middleware::from_fn_with_state(state.clone(), render_errors))
+            .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
+            .with_state(state);
+
+        let response = app
+            .oneshot(
+                Request::get("/users/not-a-uuid")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body: Value =
+            serde_json::from_slice(&to_bytes(response.into_body(), usize::MAX).await.unwrap())
+                .unwrap();
+        assert_eq!(body["error"]["code"], "bad_request");
+        assert!(body["error"]["request_id"].is_string(), "{body}");
+    }
+}
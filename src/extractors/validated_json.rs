@@ -1,13 +1,16 @@
-use axum::Json;
+use axum::body::Bytes;
 use axum::extract::{FromRequest, Request};
+use axum::http::header;
 use serde::de::DeserializeOwned;
+use serde_path_to_error::Track;
 use validator::Validate;
 
-use crate::errors::AppError;
+use crate::errors::{AppError, JsonError};
 
 /// An Axum extractor that deserializes JSON and then runs `validator` checks.
-/// Returns `AppError::BadRequest` on deserialization failure and
-/// `AppError::Validation` if the payload fails validation rules.
+/// Returns `AppError::InvalidJson` (with the path of the offending member) on
+/// deserialization failure and `AppError::Validation` if the payload fails
+/// validation rules.
 pub struct ValidatedJson<T>(pub T);
 
 impl<S, T> FromRequest<S> for ValidatedJson<T>
@@ -18,12 +21,94 @@ where
     type Rejection = AppError;
 
     async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
-        let Json(value) = Json::<T>::from_request(req, state)
+        let is_json = req
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.split(';').next())
+            .map(str::trim)
+            .is_some_and(|mime| {
+                mime.eq_ignore_ascii_case("application/json") || mime.ends_with("+json")
+            });
+
+        if !is_json {
+            return Err(AppError::UnsupportedMediaType(
+                "expected content type application/json".into(),
+            ));
+        }
+
+        let body = Bytes::from_request(req, state)
             .await
-            .map_err(|e| AppError::BadRequest(e.to_string()))?;
+            .map_err(AppError::from)?;
+
+        let value: T = from_slice(&body)?;
 
         value.validate()?;
 
         Ok(ValidatedJson(value))
     }
 }
+
+/// Deserializes a JSON document, recording the path of the member that
+/// failed. Like `axum::Json`, rejects anything but whitespace after it.
+pub fn from_slice<T: DeserializeOwned>(body: &[u8]) -> Result<T, JsonError> {
+    let mut deserializer = serde_json::Deserializer::from_slice(body);
+    let value = serde_path_to_error::deserialize(&mut deserializer)?;
+    deserializer
+        .end()
+        .map_err(|e| JsonError::new(Track::new().path(), e))?;
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Debug, Deserialize, Validate)]
+    struct Name {
+        #[validate(length(min = 1))]
+        name: String,
+    }
+
+    async fn extract(body: &'static str) -> Result<Name, AppError> {
+        let req = Request::builder()
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body))
+            .unwrap();
+        ValidatedJson::from_request(req, &())
+            .await
+            .map(|json| json.0)
+    }
+
+    #[tokio::test]
+    async fn accepts_a_document_followed_by_whitespace() {
+        let name = extract("{\"name\":\"Ada\"} \n").await.unwrap();
+        assert_eq!(name.name, "Ada");
+    }
+
+    #[tokio::test]
+    async fn rejects_trailing_data() {
+        match extract("{\"name\":\"Ada\"} garbage").await {
+            Err(AppError::InvalidJson(e)) => {
+                assert!(e.inner().is_syntax());
+                assert_eq!(e.path().to_string(), ".");
+            }
+            other => panic!("expected invalid JSON, got {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn reports_the_failing_member_and_validates() {
+        match extract("{\"name\":1}").await {
+            Err(AppError::InvalidJson(e)) => assert_eq!(e.path().to_string(), "name"),
+            other => panic!("expected invalid JSON, got {other:?}"),
+        }
+        assert!(matches!(
+            extract("{\"name\":\"\"}").await,
+            Err(AppError::Validation(_))
+        ));
+    }
+}
@@ -0,0 +1,29 @@
+use axum::extract::{FromRequestParts, Query};
+use axum::http::request::Parts;
+use serde::de::DeserializeOwned;
+use validator::Validate;
+
+use crate::errors::AppError;
+
+/// Query-string counterpart of `ValidatedJson`: deserializes the query and then
+/// runs `validator` checks. Returns `AppError::BadRequest` on deserialization
+/// failure and `AppError::Validation` if the parameters fail validation rules.
+pub struct ValidatedQuery<T>(pub T);
+
+impl<S, T> FromRequestParts<S> for ValidatedQuery<T>
+where
+    T: DeserializeOwned + Validate,
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Query(value) = Query::<T>::from_request_parts(parts, state)
+            .await
+            .map_err(AppError::from)?;
+
+        value.validate()?;
+
+        Ok(ValidatedQuery(value))
+    }
+}
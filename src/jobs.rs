@@ -0,0 +1,64 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use metrics_exporter_prometheus::PrometheusHandle;
+use sea_orm::DatabaseConnection;
+use tokio::task::JoinHandle;
+use tokio::time::MissedTickBehavior;
+use tokio_util::sync::CancellationToken;
+
+use crate::config::Config;
+use crate::services::storage::Storage;
+use crate::services::user as user_service;
+
+/// Spawns the background task that permanently removes profiles (and their
+/// avatars) whose soft-delete retention window has elapsed. Runs once at
+/// startup and then every `profiles.purge_interval_secs` until `shutdown` is
+/// cancelled; a purge already in progress is finished first.
+pub fn spawn_profile_purge(
+    db: DatabaseConnection,
+    storage: Arc<dyn Storage>,
+    config: &Config,
+    shutdown: CancellationToken,
+) -> JoinHandle<()> {
+    let retention = config.profile_retention();
+    let mut interval =
+        tokio::time::interval(Duration::from_secs(config.profiles.purge_interval_secs));
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                () = shutdown.cancelled() => break,
+            }
+
+            let cutoff = chrono::Utc::now().fixed_offset() - retention;
+            match user_service::purge_deleted_profiles(&db, storage.as_ref(), cutoff).await {
+                Ok(0) => {}
+                Ok(purged) => tracing::info!("Purged {purged} soft-deleted profiles"),
+                Err(e) => tracing::error!("Profile purge failed: {e}"),
+            }
+        }
+    })
+}
+
+/// Spawns the task that keeps the Prometheus recorder's histograms from
+/// growing between scrapes, until `shutdown` is cancelled.
+pub fn spawn_metrics_upkeep(
+    handle: PrometheusHandle,
+    shutdown: CancellationToken,
+) -> JoinHandle<()> {
+    let mut interval = tokio::time::interval(Duration::from_secs(5));
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                () = shutdown.cancelled() => break,
+            }
+            handle.run_upkeep();
+        }
+    })
+}
@@ -1,57 +1,309 @@
-use axum::Router;
-use axum::routing::get;
-use sea_orm::DatabaseConnection;
-use tower_http::cors::CorsLayer;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::{Router, middleware};
+use clap::Parser;
+use tower_http::request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer};
+use tower_http::services::ServeDir;
 use tower_http::trace::TraceLayer;
-use tracing_subscriber::EnvFilter;
+use utoipa::OpenApi;
+use utoipa_axum::router::OpenApiRouter;
+use utoipa_swagger_ui::SwaggerUi;
 
 mod config;
+mod cors;
 mod db;
 mod errors;
 mod extractors;
+mod jobs;
 mod models;
+mod monitoring;
+mod openapi;
+mod rate_limit;
 mod routes;
 mod services;
+mod shutdown;
+mod telemetry;
 
-use config::Config;
+use config::{Args, Config};
+use rate_limit::{MemoryStore, RateLimiter};
+use services::health::Draining;
+use services::jwks::JwksCache;
+use services::storage::{LocalStorage, Storage};
 
 #[derive(Clone)]
 pub struct AppState {
-    pub db: DatabaseConnection,
+    pub db: db::Pools,
     pub config: Config,
+    pub jwks: JwksCache,
+    pub storage: Arc<dyn Storage>,
+    pub draining: Draining,
+    pub rate_limiter: RateLimiter,
+}
+
+#[cfg(test)]
+impl AppState {
+    /// State for tests of extractors and middleware that never reach the
+    /// database, the JWKS endpoint or storage.
+    pub fn for_tests() -> Self {
+        let config = Config::for_tests();
+        AppState {
+            db: db::Pools::for_tests(sea_orm::DatabaseConnection::Disconnected),
+            jwks: JwksCache::new(&config.auth),
+            storage: Arc::new(LocalStorage::new(
+                &config.uploads.dir,
+                &config.uploads_url(),
+            )),
+            draining: Draining::default(),
+            rate_limiter: RateLimiter::new(&config, Arc::new(MemoryStore::default())),
+            config,
+        }
+    }
 }
 
 #[tokio::main]
 async fn main() {
     dotenvy::dotenv().ok();
+    let args = Args::parse();
 
-    tracing_subscriber::fmt()
-        .with_env_filter(
-            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
-        )
-        .init();
+    if args.print_config {
+        match config::describe(&args) {
+            Ok(config) => print!("{config}"),
+            Err(e) => exit_invalid(&e),
+        }
+        return;
+    }
+
+    let config = Config::load(&args).unwrap_or_else(|e| exit_invalid(&e));
+    let tracer_provider = telemetry::init(&config);
 
-    let config = Config::from_env().expect("Failed to load configuration");
-    let addr = format!("{}:{}", config.server_host, config.server_port);
+    let addr = format!("{}:{}", config.server.host, config.server.port);
 
     let db = db::connect(&config)
         .await
         .expect("Failed to connect to database");
+    if let Err(e) = db::migrate(db.primary(), config.database.migrations).await {
+        tracing::error!("Refusing to start: {e}");
+        std::process::exit(1);
+    }
 
-    let state = AppState { db, config };
+    let draining = Draining::default();
+    let shutdown = shutdown::on_signal(
+        draining.clone(),
+        Duration::from_secs(config.server.shutdown_delay_secs),
+    );
+    let shutdown_timeout = Duration::from_secs(config.server.shutdown_timeout_secs);
 
-    let app = Router::new()
-        .route("/health", get(routes::health))
-        .nest("/auth", routes::auth::router())
-        .nest("/users", routes::user::router())
-        .with_state(state)
-        .layer(CorsLayer::permissive())
-        .layer(TraceLayer::new_for_http());
+    let storage: Arc<dyn Storage> = Arc::new(LocalStorage::new(
+        &config.uploads.dir,
+        &config.uploads_url(),
+    ));
+    let mut tasks = vec![jobs::spawn_profile_purge(
+        db.primary().clone(),
+        storage.clone(),
+        &config,
+        shutdown.clone(),
+    )];
+
+    // Metrics are only recorded when there is a protected way to read them.
+    let metrics_addr = config.metrics.addr.clone();
+    let metrics = (metrics_addr.is_some() || config.metrics.token.is_some()).then(|| {
+        let handle = monitoring::install();
+        tasks.push(jobs::spawn_metrics_upkeep(handle.clone(), shutdown.clone()));
+        monitoring::router(handle, db.clone(), config.metrics.token.clone())
+    });
+
+    let cors = cors::layer(&config);
+    let jwks = JwksCache::new(&config.auth);
+    let rate_limiter = RateLimiter::new(&config, Arc::new(MemoryStore::default()));
+    let state = AppState {
+        db: db.clone(),
+        config,
+        jwks,
+        storage,
+        draining,
+        rate_limiter,
+    };
+
+    let mut app = routes(&state.config)
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            rate_limit::limit,
+        ))
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            errors::render_errors,
+        ))
+        .layer(middleware::from_fn(telemetry::record_route))
+        .layer(middleware::from_fn(monitoring::track_requests))
+        .with_state(state);
+
+    match (metrics, metrics_addr) {
+        (Some(metrics), Some(metrics_addr)) => {
+            let listener = tokio::net::TcpListener::bind(&metrics_addr)
+                .await
+                .expect("Failed to bind metrics address");
+            tracing::info!("Metrics listening on {metrics_addr}");
+            let shutdown = shutdown.clone();
+            tasks.push(tokio::spawn(async move {
+                axum::serve(listener, metrics)
+                    .with_graceful_shutdown(shutdown.cancelled_owned())
+                    .await
+                    .expect("Metrics server error");
+            }));
+        }
+        (Some(metrics), None) => app = app.merge(metrics),
+        (None, _) => {}
+    }
+
+    let app = app
+        .layer(cors)
+        // Layers added last run first: check_request_id drops a malformed
+        // client X-Request-Id, SetRequestId assigns the id (or keeps the
+        // client's), PropagateRequestId echoes it back and the trace span
+        // records it on every log line of the request.
+        .layer(
+            TraceLayer::new_for_http()
+                .make_span_with(telemetry::make_request_span)
+                .on_response(telemetry::on_response),
+        )
+        .layer(PropagateRequestIdLayer::x_request_id())
+        .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
+        .layer(middleware::from_fn(telemetry::check_request_id));
 
     let listener = tokio::net::TcpListener::bind(&addr)
         .await
         .expect("Failed to bind address");
 
     tracing::info!("Server listening on {addr}");
-    axum::serve(listener, app).await.unwrap();
+
+    // Once the token is cancelled the listener closes, in-flight requests and
+    // background jobs run to completion, and anything still going after the
+    // timeout is dropped.
+    let server = async {
+        // Peer addresses key the rate limits of anonymous clients.
+        axum::serve(
+            listener,
+            app.into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .with_graceful_shutdown(shutdown.clone().cancelled_owned())
+        .await
+        .expect("Server error");
+    };
+    let jobs = async {
+        for task in tasks {
+            let _ = task.await;
+        }
+    };
+    let drained = shutdown::drain(
+        async {
+            tokio::join!(server, jobs);
+        },
+        &shutdown,
+        shutdown_timeout,
+    )
+    .await;
+
+    if drained {
+        tracing::info!("Drained in-flight requests");
+    } else {
+        tracing::warn!(
+            "Shutdown timed out after {}s; dropping remaining requests",
+            shutdown_timeout.as_secs()
+        );
+    }
+
+    if let Err(e) = db.close().await {
+        tracing::error!("Failed to close database connections: {e}");
+    }
+
+    if let Some(provider) = tracer_provider
+        && let Err(e) = provider.shutdown()
+    {
+        tracing::error!("Failed to flush traces: {e}");
+    }
+
+    tracing::info!("Shutdown complete");
+}
+
+/// The API routes and the OpenAPI document describing them. Routes are
+/// registered through OpenApiRouter so each handler is documented.
+fn api(config: &Config) -> (Router<AppState>, utoipa::openapi::OpenApi) {
+    OpenApiRouter::with_openapi(openapi::ApiDoc::openapi())
+        .merge(routes::health::router())
+        .nest("/auth", routes::auth::router())
+        .nest("/users", routes::user::router(config))
+        .nest("/admin", routes::admin::router())
+        .split_for_parts()
+}
+
+/// Every route of the public listener except `/metrics`: the API, its docs
+/// and uploaded files.
+fn routes(config: &Config) -> Router<AppState> {
+    let (api, openapi) = api(config);
+    api.merge(SwaggerUi::new("/docs").url("/openapi.json", openapi))
+        .nest_service("/uploads", ServeDir::new(&config.uploads.dir))
+}
+
+/// Configuration errors are reported before logging is set up, so they go
+/// to stderr as plain text.
+fn exit_invalid(error: &config::ConfigError) -> ! {
+    eprintln!("{error}");
+    std::process::exit(2)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use super::*;
+
+    /// Routes deliberately left out of the OpenAPI document. `/metrics` is
+    /// not among them because it is served by `monitoring::router`, which
+    /// may listen on its own address.
+    const UNDOCUMENTED: &[&str] = &[
+        "/docs",
+        "/docs/",
+        "/docs/{*rest}",
+        "/openapi.json",
+        "/uploads",
+        "/uploads/",
+        "/uploads/{*__private__axum_nest_tail_param}",
+    ];
+
+    /// Paths registered on `router`. axum has no public way to list them, but
+    /// its Debug output includes each path as a quoted string, followed by
+    /// the fallback's own catch-all paths.
+    fn registered_paths(router: &Router<AppState>) -> BTreeSet<String> {
+        let debug = format!("{router:?}");
+        let (paths, _fallback) = debug
+            .split_once("fallback_router")
+            .expect("Router's Debug output lists the fallback router");
+        paths
+            .split("RouteId(")
+            .skip(1)
+            .filter_map(|entry| entry.split_once("): \"")?.1.split_once('"'))
+            .map(|(path, _)| path.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn every_route_is_documented() {
+        let config = Config::for_tests();
+        let (_, openapi) = api(&config);
+        let documented: BTreeSet<String> = openapi.paths.paths.into_keys().collect();
+
+        let registered = registered_paths(&routes(&config));
+        assert!(registered.contains("/users/me"), "{registered:?}");
+
+        let undocumented: Vec<&String> = registered
+            .iter()
+            .filter(|path| !documented.contains(*path) && !UNDOCUMENTED.contains(&path.as_str()))
+            .collect();
+        assert!(
+            undocumented.is_empty(),
+            "routes missing from the OpenAPI document: {undocumented:?}"
+        );
+    }
 }
@@ -14,6 +14,12 @@ pub struct Model {
     pub bio: Option<String>,
     pub created_at: DateTimeWithTimeZone,
     pub updated_at: DateTimeWithTimeZone,
+    /// Set when the profile is soft-deleted; the row is purged once the
+    /// retention window has passed.
+    pub deleted_at: Option<DateTimeWithTimeZone>,
+    /// `auth_id` of whoever soft-deleted the profile: its owner, or an
+    /// operator through the admin API.
+    pub deleted_by: Option<String>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -0,0 +1,137 @@
+use std::time::Instant;
+
+use axum::Router;
+use axum::extract::{MatchedPath, Request, State};
+use axum::http::{StatusCode, header};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use metrics::{counter, gauge, histogram};
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
+use sea_orm::DatabaseConnection;
+
+use crate::db::Pools;
+
+/// Histogram buckets (seconds) shared by HTTP and database timings.
+const LATENCY_BUCKETS: [f64; 12] = [
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0,
+];
+
+/// Installs the global Prometheus recorder. Until this runs, every metric
+/// macro is a no-op, so metrics cost nothing when the endpoint is disabled.
+pub fn install() -> PrometheusHandle {
+    PrometheusBuilder::new()
+        .set_buckets_for_metric(Matcher::Suffix("_seconds".into()), &LATENCY_BUCKETS)
+        .expect("latency buckets are not empty")
+        .install_recorder()
+        .expect("Failed to install metrics recorder")
+}
+
+#[derive(Clone)]
+struct MetricsState {
+    handle: PrometheusHandle,
+    db: Pools,
+    token: Option<String>,
+}
+
+/// `GET /metrics` in the Prometheus text format. When `token` is set, scrapes
+/// must send it as `Authorization: Bearer <token>`.
+pub fn router(handle: PrometheusHandle, db: Pools, token: Option<String>) -> Router {
+    Router::new()
+        .route("/metrics", get(render))
+        .with_state(MetricsState { handle, db, token })
+}
+
+async fn render(State(state): State<MetricsState>, req: Request) -> Response {
+    if let Some(expected) = &state.token {
+        let given = req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .unwrap_or_default();
+
+        if !constant_time_eq(given.as_bytes(), expected.as_bytes()) {
+            return StatusCode::UNAUTHORIZED.into_response();
+        }
+    }
+
+    record_pool_stats(&state.db);
+
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        state.handle.render(),
+    )
+        .into_response()
+}
+
+/// Pool gauges are sampled at scrape time rather than on every checkout.
+fn record_pool_stats(pools: &Pools) {
+    record_pool("primary", pools.primary());
+    if let Some(replica) = pools.replica() {
+        record_pool("replica", replica);
+    }
+}
+
+fn record_pool(role: &'static str, db: &DatabaseConnection) {
+    let pool = db.get_postgres_connection_pool();
+    let idle = pool.num_idle();
+    let open = pool.size() as usize;
+
+    gauge!("db_pool_connections", "pool" => role, "state" => "idle").set(idle as f64);
+    gauge!("db_pool_connections", "pool" => role, "state" => "in_use")
+        .set(open.saturating_sub(idle) as f64);
+    gauge!("db_pool_max_connections", "pool" => role)
+        .set(f64::from(pool.options().get_max_connections()));
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Counts requests and records their latency by method, matched route
+/// template and status. Added with `Router::layer`, so it runs after routing;
+/// requests that match no route are labelled `unmatched`.
+pub async fn track_requests(req: Request, next: Next) -> Response {
+    let start = Instant::now();
+    let method = req.method().to_string();
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or("unmatched", MatchedPath::as_str)
+        .to_string();
+
+    let response = next.run(req).await;
+
+    let labels = [
+        ("method", method),
+        ("route", route),
+        ("status", response.status().as_u16().to_string()),
+    ];
+    counter!("http_requests_total", &labels).increment(1);
+    histogram!("http_request_duration_seconds", &labels).record(start.elapsed());
+
+    response
+}
+
+/// Records the duration of a database-backed service call when dropped, so
+/// early returns through `?` are measured too.
+pub struct QueryTimer {
+    query: &'static str,
+    start: Instant,
+}
+
+impl QueryTimer {
+    pub fn start(query: &'static str) -> Self {
+        QueryTimer {
+            query,
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Drop for QueryTimer {
+    fn drop(&mut self) {
+        histogram!("db_query_duration_seconds", "query" => self.query).record(self.start.elapsed());
+    }
+}
@@ -0,0 +1,39 @@
+use utoipa::openapi::security::{Http, HttpAuthScheme, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+
+use crate::errors::{ErrorCode, ErrorResponse, ProblemDetails};
+
+/// Root of the generated OpenAPI document. Paths are not listed here: each
+/// router registers its handlers with `utoipa_axum::routes!`, which adds the
+/// route and its `#[utoipa::path]` documentation in the same call.
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        description = "User profiles on top of Supabase Auth.",
+        license(name = "MIT")
+    ),
+    modifiers(&BearerAuth),
+    security(("bearer_auth" = [])),
+    components(schemas(ErrorResponse, ProblemDetails, ErrorCode)),
+    tags(
+        (name = "health", description = "Service status"),
+        (name = "auth", description = "Login callback"),
+        (name = "users", description = "User profiles"),
+        (name = "admin", description = "Operator endpoints, require the `admin` role"),
+    )
+)]
+pub struct ApiDoc;
+
+/// Declares the Supabase access token as a bearer scheme. Every operation
+/// requires it unless its path overrides `security`.
+struct BearerAuth;
+
+impl Modify for BearerAuth {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        let mut scheme = Http::new(HttpAuthScheme::Bearer);
+        scheme.bearer_format = Some("JWT".to_string());
+        scheme.description = Some("Supabase access token".to_string());
+        components.add_security_scheme("bearer_auth", SecurityScheme::Http(scheme));
+    }
+}
@@ -0,0 +1,410 @@
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use axum::extract::{ConnectInfo, MatchedPath, Request, State};
+use axum::http::request::Parts;
+use axum::http::{HeaderName, HeaderValue, Method};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+
+use crate::AppState;
+use crate::config::Config;
+use crate::errors::AppError;
+use crate::extractors::auth::AuthUser;
+
+/// A token bucket: up to `burst` requests at once, refilled evenly so that
+/// `burst` requests are allowed per `period`. Written as `20/m`, `5/s` or
+/// `100/15m`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RateLimit {
+    pub burst: u32,
+    pub period: Duration,
+}
+
+impl RateLimit {
+    fn refill_per_sec(self) -> f64 {
+        f64::from(self.burst) / self.period.as_secs_f64()
+    }
+}
+
+impl FromStr for RateLimit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("{s:?} is not a rate limit (e.g. 20/m, 5/s, 100/15m)");
+
+        let (burst, period) = s.trim().split_once('/').ok_or_else(invalid)?;
+        let burst: u32 = burst.trim().parse().map_err(|_| invalid())?;
+
+        let period = period.trim();
+        let unit_at = period.len().checked_sub(1).ok_or_else(invalid)?;
+        let (count, unit) = period.split_at(unit_at);
+        let count: u64 = if count.is_empty() {
+            1
+        } else {
+            count.parse().map_err(|_| invalid())?
+        };
+        let unit_secs = match unit {
+            "s" => 1,
+            "m" => 60,
+            "h" => 3600,
+            _ => return Err(invalid()),
+        };
+
+        if burst == 0 || count == 0 {
+            return Err(format!("{s:?} must allow at least one request per period"));
+        }
+
+        Ok(RateLimit {
+            burst,
+            period: Duration::from_secs(count * unit_secs),
+        })
+    }
+}
+
+/// Parses `rate_limit.routes`: comma-separated `METHOD /route=limit` entries,
+/// where the route is the template as registered (`/admin/users/{id}`).
+pub fn parse_route_limits(list: &str) -> Result<BTreeMap<String, RateLimit>, String> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let invalid = || format!("{entry:?} is not `METHOD /route=limit`");
+            let (route, limit) = entry.split_once('=').ok_or_else(invalid)?;
+            let (method, path) = route.trim().split_once(' ').ok_or_else(invalid)?;
+            let method = Method::from_bytes(method.to_ascii_uppercase().as_bytes())
+                .map_err(|_| invalid())?;
+            let path = path.trim();
+            if !path.starts_with('/') {
+                return Err(invalid());
+            }
+            Ok((route_key(&method, path), limit.parse()?))
+        })
+        .collect()
+}
+
+fn route_key(method: &Method, route: &str) -> String {
+    format!("{method} {route}")
+}
+
+/// Outcome of taking a token from a bucket.
+pub struct Decision {
+    pub allowed: bool,
+    /// Requests left before the bucket is empty.
+    pub remaining: u32,
+    /// Time until the bucket is full again.
+    pub reset: Duration,
+    /// Time until the next request would be allowed; zero when allowed.
+    pub retry_after: Duration,
+}
+
+/// Backend holding the token buckets. The in-memory store limits each
+/// instance separately; a shared backend (e.g. Redis) can implement the same
+/// trait to enforce limits across replicas.
+#[async_trait]
+pub trait RateLimitStore: Send + Sync {
+    /// Takes one token from the bucket `key`, which `limit` governs.
+    async fn acquire(&self, key: &str, limit: RateLimit) -> Decision;
+}
+
+/// Buckets in a process-local map. Buckets that have refilled completely are
+/// indistinguishable from new ones, so they are dropped as the map grows.
+pub struct MemoryStore {
+    buckets: Mutex<Buckets>,
+}
+
+struct Buckets {
+    by_key: HashMap<String, Bucket>,
+    prune_at: usize,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    full_at: Instant,
+}
+
+const MIN_PRUNE_AT: usize = 1024;
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        MemoryStore {
+            buckets: Mutex::new(Buckets {
+                by_key: HashMap::new(),
+                prune_at: MIN_PRUNE_AT,
+            }),
+        }
+    }
+}
+
+#[async_trait]
+impl RateLimitStore for MemoryStore {
+    async fn acquire(&self, key: &str, limit: RateLimit) -> Decision {
+        let now = Instant::now();
+        let capacity = f64::from(limit.burst);
+        let rate = limit.refill_per_sec();
+
+        let mut buckets = self.buckets.lock().expect("rate limit store poisoned");
+        if buckets.by_key.len() >= buckets.prune_at {
+            buckets.by_key.retain(|_, bucket| bucket.full_at > now);
+            buckets.prune_at = (buckets.by_key.len() * 2).max(MIN_PRUNE_AT);
+        }
+
+        let bucket = buckets.by_key.entry(key.to_string()).or_insert(Bucket {
+            tokens: capacity,
+            updated: now,
+            full_at: now,
+        });
+
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(capacity);
+        bucket.updated = now;
+
+        let allowed = bucket.tokens >= 1.0;
+        if allowed {
+            bucket.tokens -= 1.0;
+        }
+
+        let reset = Duration::from_secs_f64((capacity - bucket.tokens) / rate);
+        bucket.full_at = now + reset;
+
+        Decision {
+            allowed,
+            remaining: bucket.tokens.floor() as u32,
+            reset,
+            retry_after: if allowed {
+                Duration::ZERO
+            } else {
+                Duration::from_secs_f64((1.0 - bucket.tokens) / rate)
+            },
+        }
+    }
+}
+
+/// The configured limits and the store enforcing them. Cheap to clone.
+#[derive(Clone)]
+pub struct RateLimiter {
+    store: Arc<dyn RateLimitStore>,
+    default: Option<RateLimit>,
+    routes: Arc<BTreeMap<String, RateLimit>>,
+    trust_forwarded_for: bool,
+}
+
+impl RateLimiter {
+    pub fn new(config: &Config, store: Arc<dyn RateLimitStore>) -> Self {
+        RateLimiter {
+            store,
+            default: config.rate_limit.default,
+            routes: Arc::new(config.rate_limit.routes.clone()),
+            trust_forwarded_for: config.rate_limit.trust_forwarded_for,
+        }
+    }
+
+    /// The bucket scope and limit for a request. Routes with their own limit
+    /// get their own bucket; every other route shares the default one.
+    fn rule(&self, method: &Method, route: Option<&str>) -> Option<(String, RateLimit)> {
+        route
+            .map(|route| route_key(method, route))
+            .and_then(|key| self.routes.get(&key).map(|limit| (key, *limit)))
+            .or_else(|| self.default.map(|limit| ("default".to_string(), limit)))
+    }
+
+    /// The client address: the peer, or with `rate_limit.trust_forwarded_for`
+    /// the last `X-Forwarded-For` entry, which is the one our proxy appended.
+    fn client_ip(&self, parts: &Parts) -> String {
+        let forwarded = self
+            .trust_forwarded_for
+            .then(|| parts.headers.get("x-forwarded-for"))
+            .flatten()
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.rsplit(',').next())
+            .map(str::trim)
+            .filter(|ip| !ip.is_empty());
+
+        match forwarded {
+            Some(ip) => ip.to_string(),
+            None => parts
+                .extensions
+                .get::<ConnectInfo<SocketAddr>>()
+                .map_or_else(|| "unknown".to_string(), |info| info.0.ip().to_string()),
+        }
+    }
+}
+
+/// `RateLimit-Limit`, `RateLimit-Remaining` and `RateLimit-Reset`, in the
+/// order `limit` fills them in.
+pub const RATE_LIMIT_HEADERS: [&str; 3] =
+    ["ratelimit-limit", "ratelimit-remaining", "ratelimit-reset"];
+
+/// Applies the rate limit of the matched route, keyed by the authenticated
+/// user or, for anonymous requests, the client IP. Limited responses carry
+/// `RateLimit-Limit`, `RateLimit-Remaining` and `RateLimit-Reset`; rejected
+/// ones are `429` with `Retry-After`. Health probes are never limited.
+///
+/// Added with `Router::layer` inside `render_errors`, so it sees the matched
+/// route and its `429`s get the usual error rendering.
+pub async fn limit(State(state): State<AppState>, req: Request, next: Next) -> Response {
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_string());
+    if route.as_deref().is_some_and(|r| r.starts_with("/health")) {
+        return next.run(req).await;
+    }
+
+    let limiter = &state.rate_limiter;
+    let Some((scope, limit)) = limiter.rule(req.method(), route.as_deref()) else {
+        return next.run(req).await;
+    };
+
+    let (mut parts, body) = req.into_parts();
+    let client = match AuthUser::identify(&mut parts, &state).await {
+        Some(user) => format!("user:{}", user.id),
+        None => format!("ip:{}", limiter.client_ip(&parts)),
+    };
+
+    let decision = limiter
+        .store
+        .acquire(&format!("{scope}|{client}"), limit)
+        .await;
+
+    let mut response = if decision.allowed {
+        next.run(Request::from_parts(parts, body)).await
+    } else {
+        metrics::counter!("rate_limit_rejections_total", "scope" => scope).increment(1);
+        AppError::RateLimited {
+            retry_after: ceil_secs(decision.retry_after),
+        }
+        .into_response()
+    };
+
+    let headers = response.headers_mut();
+    let values = [
+        limit.burst.into(),
+        decision.remaining.into(),
+        ceil_secs(decision.reset),
+    ];
+    for (name, value) in RATE_LIMIT_HEADERS.into_iter().zip(values) {
+        headers.insert(HeaderName::from_static(name), HeaderValue::from(value));
+    }
+
+    response
+}
+
+fn ceil_secs(duration: Duration) -> u64 {
+    duration.as_secs_f64().ceil() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limit(s: &str) -> RateLimit {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_rate_limits() {
+        let per = |burst, secs| RateLimit {
+            burst,
+            period: Duration::from_secs(secs),
+        };
+        assert_eq!(limit("20/m"), per(20, 60));
+        assert_eq!(limit(" 5 / s "), per(5, 1));
+        assert_eq!(limit("100/15m"), per(100, 900));
+        assert_eq!(limit("1000/2h"), per(1000, 7200));
+
+        for invalid in [
+            "", "20", "20/", "/m", "20/d", "x/m", "20/xm", "-1/m", "20/1.5m",
+        ] {
+            assert!(invalid.parse::<RateLimit>().is_err(), "{invalid:?}");
+        }
+        for empty in ["0/m", "5/0m"] {
+            let error = empty.parse::<RateLimit>().unwrap_err();
+            assert!(error.contains("at least one request"), "{error}");
+        }
+    }
+
+    #[test]
+    fn parses_route_limits() {
+        let routes =
+            parse_route_limits("post /auth/callback=10/m, PUT /users/{id} = 2/s ,").unwrap();
+        assert_eq!(
+            routes.into_iter().collect::<Vec<_>>(),
+            [
+                ("POST /auth/callback".to_string(), limit("10/m")),
+                ("PUT /users/{id}".to_string(), limit("2/s")),
+            ]
+        );
+        assert!(parse_route_limits("").unwrap().is_empty());
+
+        for invalid in [
+            "POST /auth/callback",
+            "/auth/callback=10/m",
+            "POST auth/callback=10/m",
+            "P@ST /x=10/m",
+            "POST /x=often",
+        ] {
+            assert!(parse_route_limits(invalid).is_err(), "{invalid:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn buckets_allow_a_burst_then_reject() {
+        let store = MemoryStore::default();
+        let limit = limit("3/h");
+
+        for remaining in [2, 1, 0] {
+            let decision = store.acquire("a", limit).await;
+            assert!(decision.allowed);
+            assert_eq!(decision.remaining, remaining);
+            assert_eq!(decision.retry_after, Duration::ZERO);
+        }
+
+        let rejected = store.acquire("a", limit).await;
+        assert!(!rejected.allowed);
+        assert_eq!(rejected.remaining, 0);
+        // One token takes 20 minutes to refill, the whole bucket an hour.
+        assert!(rejected.retry_after.abs_diff(Duration::from_secs(1200)) < Duration::from_secs(1));
+        assert!(rejected.reset.abs_diff(Duration::from_secs(3600)) < Duration::from_secs(1));
+
+        // Other keys have their own bucket.
+        assert!(store.acquire("b", limit).await.allowed);
+    }
+
+    #[tokio::test]
+    async fn buckets_refill_over_time() {
+        let store = MemoryStore::default();
+        let limit = limit("10/s");
+
+        for _ in 0..10 {
+            assert!(store.acquire("a", limit).await.allowed);
+        }
+        assert!(!store.acquire("a", limit).await.allowed);
+
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        let decision = store.acquire("a", limit).await;
+        assert!(decision.allowed);
+        assert!(decision.remaining >= 1);
+    }
+
+    #[tokio::test]
+    async fn full_buckets_are_pruned() {
+        let store = MemoryStore::default();
+        let limit = limit("1000/s");
+
+        for key in 0..MIN_PRUNE_AT {
+            store.acquire(&key.to_string(), limit).await;
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        store.acquire("new", limit).await;
+
+        let buckets = store.buckets.lock().unwrap();
+        assert_eq!(buckets.by_key.len(), 1);
+        assert_eq!(buckets.prune_at, MIN_PRUNE_AT);
+    }
+}
@@ -0,0 +1,205 @@
+use axum::Json;
+use axum::extract::State;
+use axum::http::StatusCode;
+use sea_orm::TransactionTrait;
+use serde::Deserialize;
+use utoipa::IntoParams;
+use utoipa_axum::router::OpenApiRouter;
+use utoipa_axum::routes;
+use uuid::Uuid;
+use validator::Validate;
+
+use crate::AppState;
+use crate::errors::{AppError, ErrorResponse};
+use crate::extractors::authorization::{Admin, DeleteProfiles, RequirePermission, RequireRole};
+use crate::extractors::pagination::{Page, Pagination, PaginationQuery};
+use crate::extractors::path::Path;
+use crate::extractors::validated_json::ValidatedJson;
+use crate::extractors::validated_query::ValidatedQuery;
+use crate::routes::ProfileResponse;
+use crate::routes::user::{ListProfilesQuery, UpdateProfileRequest};
+use crate::services::user as user_service;
+
+#[derive(Deserialize, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct LookupUserQuery {
+    #[validate(length(min = 1))]
+    pub auth_id: Option<String>,
+    #[validate(email)]
+    pub email: Option<String>,
+}
+
+/// Operator endpoints for managing any user's profile. Every handler requires
+/// the `admin` role; deleting also requires the `profiles:delete` permission.
+pub fn router() -> OpenApiRouter<AppState> {
+    OpenApiRouter::new()
+        .routes(routes!(list_users))
+        .routes(routes!(lookup_user))
+        .routes(routes!(get_user, update_user, delete_user))
+}
+
+/// Same listing as `GET /users` (filters, cursor pagination) for operators.
+#[utoipa::path(
+    get,
+    path = "/users",
+    tag = "admin",
+    params(PaginationQuery, ListProfilesQuery),
+    responses(
+        (status = 200, description = "One page of profiles", body = Page<ProfileResponse>),
+        (status = 400, description = "Malformed query or cursor", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+        (status = 403, description = "Caller is not an admin", body = ErrorResponse),
+        (status = 422, description = "Invalid query parameters", body = ErrorResponse),
+    )
+)]
+async fn list_users(
+    State(state): State<AppState>,
+    admin: RequireRole<Admin>,
+    pagination: Pagination,
+    ValidatedQuery(query): ValidatedQuery<ListProfilesQuery>,
+) -> Result<Json<Page<ProfileResponse>>, AppError> {
+    let profiles = user_service::list_profiles(
+        state.db.read_for(&admin.id),
+        &query.into(),
+        pagination.after()?,
+        pagination.order,
+        pagination.fetch_limit(),
+    )
+    .await?;
+
+    let page = pagination.page(profiles, |p| (p.created_at, p.id));
+
+    Ok(Json(page.map(Into::into)))
+}
+
+/// GET /admin/users/lookup?auth_id=... or ?email=... (exactly one of them).
+#[utoipa::path(
+    get,
+    path = "/users/lookup",
+    tag = "admin",
+    params(LookupUserQuery),
+    responses(
+        (status = 200, description = "The matching profile", body = ProfileResponse),
+        (status = 400, description = "Not exactly one of `auth_id` or `email`", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+        (status = 403, description = "Caller is not an admin", body = ErrorResponse),
+        (status = 404, description = "No such profile", body = ErrorResponse),
+        (status = 422, description = "Invalid query parameters", body = ErrorResponse),
+    )
+)]
+async fn lookup_user(
+    State(state): State<AppState>,
+    admin: RequireRole<Admin>,
+    ValidatedQuery(query): ValidatedQuery<LookupUserQuery>,
+) -> Result<Json<ProfileResponse>, AppError> {
+    let db = state.db.read_for(&admin.id);
+    let profile = match (query.auth_id, query.email) {
+        (Some(auth_id), None) => user_service::find_by_auth_id(db, &auth_id).await?,
+        (None, Some(email)) => user_service::find_by_email(db, &email).await?,
+        _ => {
+            return Err(AppError::BadRequest(
+                "exactly one of auth_id or email is required".into(),
+            ));
+        }
+    };
+
+    let profile = profile.ok_or(AppError::ProfileNotFound)?;
+
+    Ok(Json(profile.into()))
+}
+
+#[utoipa::path(
+    get,
+    path = "/users/{id}",
+    tag = "admin",
+    params(("id" = Uuid, Path, description = "Profile id")),
+    responses(
+        (status = 200, description = "The profile", body = ProfileResponse),
+        (status = 400, description = "Malformed id", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+        (status = 403, description = "Caller is not an admin", body = ErrorResponse),
+        (status = 404, description = "No such profile", body = ErrorResponse),
+    )
+)]
+async fn get_user(
+    State(state): State<AppState>,
+    admin: RequireRole<Admin>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<ProfileResponse>, AppError> {
+    let profile = user_service::find_by_id(state.db.read_for(&admin.id), id)
+        .await?
+        .ok_or(AppError::ProfileNotFound)?;
+
+    Ok(Json(profile.into()))
+}
+
+#[utoipa::path(
+    put,
+    path = "/users/{id}",
+    tag = "admin",
+    params(("id" = Uuid, Path, description = "Profile id")),
+    request_body = UpdateProfileRequest,
+    responses(
+        (status = 200, description = "The updated profile", body = ProfileResponse),
+        (status = 400, description = "Malformed id or JSON, or wrong member type", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+        (status = 403, description = "Caller is not an admin", body = ErrorResponse),
+        (status = 404, description = "No such profile", body = ErrorResponse),
+        (status = 415, description = "Not `application/json`", body = ErrorResponse),
+        (status = 422, description = "Invalid body", body = ErrorResponse),
+    )
+)]
+async fn update_user(
+    State(state): State<AppState>,
+    admin: RequireRole<Admin>,
+    Path(id): Path<Uuid>,
+    ValidatedJson(body): ValidatedJson<UpdateProfileRequest>,
+) -> Result<Json<ProfileResponse>, AppError> {
+    let txn = state.db.primary().begin().await?;
+    let profile = user_service::find_by_id(&txn, id)
+        .await?
+        .ok_or(AppError::ProfileNotFound)?;
+    // The edited user, not the admin, is the one who must read it back.
+    state.db.record_write(&profile.auth_id);
+
+    let profile = user_service::update_profile(&txn, &profile.auth_id, body.into(), None).await?;
+    txn.commit().await?;
+
+    tracing::info!(admin_id = %admin.id, profile_id = %id, "Admin updated profile");
+
+    Ok(Json(profile.into()))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/users/{id}",
+    tag = "admin",
+    params(("id" = Uuid, Path, description = "Profile id")),
+    responses(
+        (status = 204, description = "Profile soft-deleted; signing in again does not restore it"),
+        (status = 400, description = "Malformed id", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+        (status = 403, description = "Caller is not an admin or lacks `profiles:delete`", body = ErrorResponse),
+        (status = 404, description = "No such profile", body = ErrorResponse),
+    )
+)]
+async fn delete_user(
+    State(state): State<AppState>,
+    admin: RequireRole<Admin>,
+    _: RequirePermission<DeleteProfiles>,
+    Path(id): Path<Uuid>,
+) -> Result<StatusCode, AppError> {
+    let txn = state.db.primary().begin().await?;
+    let profile = user_service::find_by_id(&txn, id)
+        .await?
+        .ok_or(AppError::ProfileNotFound)?;
+    // The edited user, not the admin, is the one who must read it back.
+    state.db.record_write(&profile.auth_id);
+
+    user_service::delete_profile(&txn, &profile.auth_id, &admin.id).await?;
+    txn.commit().await?;
+
+    tracing::info!(admin_id = %admin.id, profile_id = %id, "Admin deleted profile");
+
+    Ok(StatusCode::NO_CONTENT)
+}
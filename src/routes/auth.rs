@@ -1,27 +1,155 @@
+use axum::Json;
 use axum::extract::State;
-use axum::routing::post;
-use axum::{Json, Router};
+use utoipa_axum::router::OpenApiRouter;
+use utoipa_axum::routes;
 
 use crate::AppState;
-use crate::errors::AppError;
+use crate::errors::{AppError, ErrorResponse};
 use crate::extractors::auth::AuthUser;
 use crate::routes::ProfileResponse;
-use crate::services::user as user_service;
+use crate::services::avatar;
+use crate::services::user::{self as user_service, Restore};
 
-pub fn router() -> Router<AppState> {
-    Router::new().route("/callback", post(auth_callback))
+pub fn router() -> OpenApiRouter<AppState> {
+    OpenApiRouter::new().routes(routes!(auth_callback))
 }
 
 /// Called by the client right after a successful Supabase login.
-/// Finds the existing profile or creates a new one (upsert by auth_id).
+/// Finds the existing profile, restores one the user recently soft-deleted,
+/// or creates a new one (upsert by auth_id). A profile an operator deleted
+/// is refused until it is purged. Safe to call concurrently.
+#[utoipa::path(
+    post,
+    path = "/callback",
+    tag = "auth",
+    responses(
+        (status = 200, description = "The caller's profile", body = ProfileResponse),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+        (status = 403, description = "The profile was deleted by an operator", body = ErrorResponse),
+    )
+)]
 async fn auth_callback(
     State(state): State<AppState>,
     auth_user: AuthUser,
 ) -> Result<Json<ProfileResponse>, AppError> {
-    let profile = match user_service::find_by_auth_id(&state.db, &auth_user.id).await? {
-        Some(existing) => existing,
-        None => user_service::create_profile(&state.db, auth_user.id, auth_user.email).await?,
+    // Read from the primary: the client usually fetches the profile straight
+    // after signing in, and a replica may not have it yet.
+    let db = state.db.write_for(&auth_user.id);
+    if let Some(existing) = user_service::find_by_auth_id(db, &auth_user.id).await? {
+        return Ok(Json(existing.into()));
+    }
+
+    // Restoring may purge an expired profile so a new one can take its
+    // auth_id; both happen in one transaction. Parallel callbacks for a new
+    // user meet in `create_profile`'s upsert and get the same row.
+    let txn = state.db.begin_for(&auth_user.id).await?;
+    let retention = state.config.profile_retention();
+    let restore = user_service::restore_profile(&txn, &auth_user.id, retention).await?;
+    let purged = match restore {
+        Restore::Purged(id) => Some(id),
+        Restore::Restored(_) | Restore::DeletedByOperator | Restore::NotDeleted => None,
+    };
+    let profile = match restore {
+        Restore::Restored(restored) => {
+            tracing::info!(profile_id = %restored.id, "Restored soft-deleted profile");
+            restored
+        }
+        Restore::DeletedByOperator => {
+            return Err(AppError::Forbidden(
+                "profile was deleted by an operator".into(),
+            ));
+        }
+        Restore::Purged(_) | Restore::NotDeleted => {
+            user_service::create_profile(&txn, auth_user.id, auth_user.email).await?
+        }
     };
+    txn.commit().await?;
+
+    // Only once the purge is committed: a rollback would bring the expired
+    // profile back without its avatars.
+    if let Some(purged) = purged {
+        avatar::remove_all(state.storage.as_ref(), purged).await;
+    }
 
     Ok(Json(profile.into()))
 }
+
+#[cfg(test)]
+mod tests {
+    use sea_orm::{DatabaseConnection, EntityTrait};
+
+    use super::*;
+    use crate::db;
+    use crate::models::profile;
+
+    fn user(auth_id: &str) -> AuthUser {
+        AuthUser {
+            id: auth_id.into(),
+            email: "user@example.com".into(),
+            role: "authenticated".into(),
+            roles: Vec::new(),
+            permissions: Vec::new(),
+        }
+    }
+
+    fn state(db: &DatabaseConnection) -> AppState {
+        AppState {
+            db: db::Pools::for_tests(db.clone()),
+            ..AppState::for_tests()
+        }
+    }
+
+    async fn sign_in(db: &DatabaseConnection, auth_id: &str) -> Result<ProfileResponse, AppError> {
+        auth_callback(State(state(db)), user(auth_id))
+            .await
+            .map(|Json(profile)| profile)
+    }
+
+    /// Signs in a new user, has them deleted by `deleted_by` (themselves if
+    /// `None`), signs them in again and looks up their active profile. The
+    /// callback commits, so the profile is removed afterwards.
+    async fn sign_in_after_deletion(
+        db: &DatabaseConnection,
+        deleted_by: Option<&str>,
+    ) -> (
+        ProfileResponse,
+        Result<ProfileResponse, AppError>,
+        Option<profile::Model>,
+    ) {
+        let auth_id = format!("test-{}", uuid::Uuid::new_v4());
+        let created = sign_in(db, &auth_id).await.unwrap();
+        let deleted_by = deleted_by.unwrap_or(&auth_id);
+        user_service::delete_profile(db, &auth_id, deleted_by)
+            .await
+            .unwrap();
+
+        let again = sign_in(db, &auth_id).await;
+        let active = user_service::find_by_auth_id(db, &auth_id).await.unwrap();
+
+        profile::Entity::delete_by_id(created.id)
+            .exec(db)
+            .await
+            .unwrap();
+        (created, again, active)
+    }
+
+    #[tokio::test]
+    async fn signing_in_restores_a_profile_the_user_deleted() {
+        let db = db::test_database().await;
+
+        let (created, again, active) = sign_in_after_deletion(&db, None).await;
+
+        assert_eq!(again.unwrap().id, created.id);
+        assert_eq!(active.unwrap().id, created.id);
+    }
+
+    #[tokio::test]
+    async fn signing_in_does_not_restore_a_profile_an_admin_deleted() {
+        let db = db::test_database().await;
+
+        let (_, again, active) = sign_in_after_deletion(&db, Some("admin-1")).await;
+
+        assert!(matches!(again, Err(AppError::Forbidden(_))));
+        assert!(active.is_none());
+    }
+}
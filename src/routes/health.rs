@@ -0,0 +1,271 @@
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use axum::Json;
+use axum::extract::State;
+use axum::http::StatusCode;
+use sea_orm::DatabaseConnection;
+use serde::Serialize;
+use utoipa::ToSchema;
+use utoipa_axum::router::OpenApiRouter;
+use utoipa_axum::routes;
+
+use crate::AppState;
+use crate::db::{self, PoolStatus};
+use crate::services::health as health_service;
+use crate::services::jwks;
+
+/// A readiness check that takes longer than this counts as failed, so a hung
+/// dependency cannot stall the probe past its own timeout.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+// The auth check fetches the JWKS; the fetch must give up before the check.
+const _: () = assert!(jwks::FETCH_TIMEOUT.as_millis() < CHECK_TIMEOUT.as_millis());
+
+pub fn router() -> OpenApiRouter<AppState> {
+    OpenApiRouter::new()
+        .routes(routes!(health))
+        .routes(routes!(live))
+        .routes(routes!(ready))
+}
+
+#[derive(Serialize, ToSchema)]
+#[schema(example = json!({ "status": "alive" }))]
+pub struct Liveness {
+    pub status: &'static str,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ReadinessStatus {
+    Ready,
+    NotReady,
+    /// Shutdown has started; checks are skipped.
+    Draining,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Readiness {
+    pub status: ReadinessStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub checks: Option<ReadinessChecks>,
+    /// Effective connection pool settings and current usage.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pool: Option<PoolStatus>,
+    /// The same for the read replica, when one is configured.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub replica_pool: Option<PoolStatus>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ReadinessChecks {
+    /// The database answers; the detail is the connection's `statement_timeout`.
+    pub database: CheckResult,
+    /// The read replica answers, when one is configured. Reads are routed to
+    /// it, so the instance is not ready without it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub replica: Option<CheckResult>,
+    /// Every migration known to this build has been applied.
+    pub migrations: CheckResult,
+    /// JWT verification keys are loaded (JWKS or the legacy secret).
+    pub auth: CheckResult,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckStatus {
+    Up,
+    Down,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct CheckResult {
+    pub status: CheckStatus,
+    pub latency_ms: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl CheckResult {
+    fn is_up(&self) -> bool {
+        matches!(self.status, CheckStatus::Up)
+    }
+}
+
+/// GET /health/live -- the process is running. Touches no dependencies, so a
+/// database outage makes the instance unready rather than getting it restarted.
+#[utoipa::path(
+    get,
+    path = "/health/live",
+    tag = "health",
+    security(()),
+    responses(
+        (status = 200, description = "The process is running", body = Liveness),
+    )
+)]
+pub async fn live() -> Json<Liveness> {
+    Json(Liveness { status: "alive" })
+}
+
+/// GET /health/ready -- whether this instance should receive traffic, with
+/// the status and latency of each dependency check.
+#[utoipa::path(
+    get,
+    path = "/health/ready",
+    tag = "health",
+    security(()),
+    responses(
+        (status = 200, description = "All checks passed", body = Readiness,
+            example = json!({
+                "status": "ready",
+                "checks": {
+                    "database": { "status": "up", "latency_ms": 0.8, "detail": "statement_timeout: 30s" },
+                    "migrations": { "status": "up", "latency_ms": 1.9,
+                        "detail": "m20261017_000003_add_profiles_deleted_by" },
+                    "auth": { "status": "up", "latency_ms": 0.1, "detail": "signing keys loaded: 2" }
+                },
+                "pool": { "size": 3, "idle": 2, "max_connections": 10, "min_connections": 0,
+                    "acquire_timeout_secs": 30, "idle_timeout_secs": 600,
+                    "max_lifetime_secs": 1800, "statement_timeout_ms": 30000 }
+            })),
+        (status = 503, description = "A check failed or the instance is shutting down",
+            body = Readiness,
+            example = json!({
+                "status": "not_ready",
+                "checks": {
+                    "database": { "status": "down", "latency_ms": 2000.4, "detail": "timed out" },
+                    "migrations": { "status": "down", "latency_ms": 2000.3, "detail": "timed out" },
+                    "auth": { "status": "up", "latency_ms": 0.1, "detail": "signing keys loaded: 2" }
+                }
+            })),
+    )
+)]
+pub async fn ready(State(state): State<AppState>) -> (StatusCode, Json<Readiness>) {
+    if state.draining.is_draining() {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(Readiness {
+                status: ReadinessStatus::Draining,
+                checks: None,
+                pool: None,
+                replica_pool: None,
+            }),
+        );
+    }
+
+    let (database, replica, migrations, auth) = tokio::join!(
+        run_check(check_database("database", state.db.primary())),
+        async {
+            match state.db.replica() {
+                Some(replica) => Some(run_check(check_database("replica", replica)).await),
+                None => None,
+            }
+        },
+        run_check(check_migrations(&state)),
+        run_check(check_auth(&state)),
+    );
+
+    let replica_up = replica.as_ref().is_none_or(CheckResult::is_up);
+    let (status, code) = if database.is_up() && replica_up && migrations.is_up() && auth.is_up() {
+        (ReadinessStatus::Ready, StatusCode::OK)
+    } else {
+        (ReadinessStatus::NotReady, StatusCode::SERVICE_UNAVAILABLE)
+    };
+
+    let checks = ReadinessChecks {
+        database,
+        replica,
+        migrations,
+        auth,
+    };
+    (
+        code,
+        Json(Readiness {
+            status,
+            checks: Some(checks),
+            pool: Some(PoolStatus::of(state.db.primary(), &state.config.database)),
+            replica_pool: state
+                .db
+                .replica()
+                .map(|replica| PoolStatus::of(replica, &state.config.database)),
+        }),
+    )
+}
+
+/// GET /health -- kept for existing monitors; same report as `/health/ready`.
+#[utoipa::path(
+    get,
+    path = "/health",
+    tag = "health",
+    security(()),
+    responses(
+        (status = 200, description = "All checks passed", body = Readiness),
+        (status = 503, description = "A check failed or the instance is shutting down",
+            body = Readiness),
+    )
+)]
+pub async fn health(state: State<AppState>) -> (StatusCode, Json<Readiness>) {
+    ready(state).await
+}
+
+/// Times a check and converts its outcome. Failure details are kept generic:
+/// the probe is public, so underlying errors are only logged.
+async fn run_check(check: impl Future<Output = Result<Option<String>, String>>) -> CheckResult {
+    let start = Instant::now();
+    let outcome = tokio::time::timeout(CHECK_TIMEOUT, check)
+        .await
+        .unwrap_or_else(|_| Err("timed out".into()));
+    let latency_ms = (start.elapsed().as_secs_f64() * 1e5).round() / 100.0;
+
+    match outcome {
+        Ok(detail) => CheckResult {
+            status: CheckStatus::Up,
+            latency_ms,
+            detail,
+        },
+        Err(detail) => CheckResult {
+            status: CheckStatus::Down,
+            latency_ms,
+            detail: Some(detail),
+        },
+    }
+}
+
+async fn check_database(name: &str, db: &DatabaseConnection) -> Result<Option<String>, String> {
+    health_service::ping_database(db)
+        .await
+        .map(|timeout| Some(format!("statement_timeout: {timeout}")))
+        .map_err(|e| {
+            tracing::warn!("Readiness: {name} check failed: {e}");
+            format!("{name} unreachable")
+        })
+}
+
+async fn check_migrations(state: &AppState) -> Result<Option<String>, String> {
+    let expected = db::latest_migration();
+    let pending = db::pending_migrations(state.db.primary())
+        .await
+        .map_err(|e| {
+            tracing::warn!("Readiness: migration check failed: {e}");
+            "migration status unavailable".to_string()
+        })?;
+
+    if pending.is_empty() {
+        Ok(Some(expected))
+    } else {
+        Err(format!(
+            "{} pending migrations, expected {expected}",
+            pending.len()
+        ))
+    }
+}
+
+async fn check_auth(state: &AppState) -> Result<Option<String>, String> {
+    match state.jwks.key_count().await {
+        0 if state.config.auth.jwt_secret.is_some() => {
+            Ok(Some("legacy secret only, JWKS unavailable".into()))
+        }
+        0 => Err("signing keys are unavailable".into()),
+        keys => Ok(Some(format!("signing keys loaded: {keys}"))),
+    }
+}
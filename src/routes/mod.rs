@@ -1,14 +1,20 @@
+pub mod admin;
 pub mod auth;
+pub mod health;
 pub mod user;
 
 use axum::Json;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
 use serde::Serialize;
+use utoipa::ToSchema;
 use uuid::Uuid;
 
+use crate::extractors::conditional::{ETag, Preconditions};
 use crate::models::profile;
 
 /// Shared response DTO for profile data returned to clients.
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct ProfileResponse {
     pub id: Uuid,
     pub auth_id: String,
@@ -31,17 +37,14 @@ impl From<profile::Model> for ProfileResponse {
     }
 }
 
-/// GET /health -- lightweight check that the DB connection is alive.
-pub async fn health(
-    axum::extract::State(state): axum::extract::State<crate::AppState>,
-) -> Result<Json<serde_json::Value>, crate::errors::AppError> {
-    use sea_orm::ConnectionTrait;
+/// Renders a profile with its `ETag`, or an empty `304 Not Modified` when the
+/// client's `If-None-Match` already names the current version.
+pub fn conditional_profile(profile: profile::Model, preconditions: &Preconditions) -> Response {
+    let etag = ETag::for_profile(&profile);
 
-    state
-        .db
-        .execute_unprepared("SELECT 1")
-        .await
-        .map_err(|e| crate::errors::AppError::Internal(format!("db health check failed: {e}")))?;
+    if preconditions.is_not_modified(&etag) {
+        return (StatusCode::NOT_MODIFIED, etag, ()).into_response();
+    }
 
-    Ok(Json(serde_json::json!({ "status": "ok" })))
+    (etag, Json(ProfileResponse::from(profile))).into_response()
 }
@@ -1,78 +1,693 @@
-use axum::extract::{Path, State};
-use axum::routing::{delete, get, put};
-use axum::{Json, Router};
+use axum::Json;
+use axum::extract::multipart::MultipartError;
+use axum::extract::{DefaultBodyLimit, State};
+use axum::http::StatusCode;
+use axum::response::Response;
+use sea_orm::prelude::DateTimeWithTimeZone;
+use serde::Serialize;
+use utoipa::{IntoParams, ToSchema};
+use utoipa_axum::router::{OpenApiRouter, UtoipaMethodRouterExt};
+use utoipa_axum::routes;
 use uuid::Uuid;
 use validator::Validate;
 
 use crate::AppState;
-use crate::errors::AppError;
+use crate::config::Config;
+use crate::errors::{AppError, ErrorResponse};
 use crate::extractors::auth::AuthUser;
+use crate::extractors::conditional::{ETag, Preconditions};
+use crate::extractors::merge_patch::{MERGE_PATCH_CONTENT_TYPE, MergePatch, nullable};
+use crate::extractors::multipart::Multipart;
+use crate::extractors::pagination::{Page, Pagination, PaginationQuery};
+use crate::extractors::path::Path;
 use crate::extractors::validated_json::ValidatedJson;
-use crate::routes::ProfileResponse;
-use crate::services::user as user_service;
+use crate::extractors::validated_query::ValidatedQuery;
+use crate::models::profile;
+use crate::routes::{ProfileResponse, conditional_profile};
+use crate::services::avatar;
+use crate::services::user::{
+    self as user_service, ProfileChanges, ProfileFilter, ProfileSearchHit,
+};
 
-#[derive(serde::Deserialize, Validate)]
+#[derive(serde::Deserialize, Validate, ToSchema)]
 pub struct UpdateProfileRequest {
     #[validate(length(min = 2, max = 100))]
+    #[schema(min_length = 2, max_length = 100)]
     pub display_name: Option<String>,
     #[validate(length(max = 500))]
+    #[schema(max_length = 500)]
     pub bio: Option<String>,
     #[validate(length(max = 2048))]
+    #[schema(max_length = 2048)]
     pub avatar_url: Option<String>,
 }
 
-pub fn router() -> Router<AppState> {
-    Router::new()
-        .route("/me", get(get_me))
-        .route("/me", put(update_me))
-        .route("/me", delete(delete_me))
-        .route("/{id}", get(get_by_id))
+impl From<UpdateProfileRequest> for ProfileChanges {
+    fn from(body: UpdateProfileRequest) -> Self {
+        ProfileChanges {
+            display_name: body.display_name.map(Some),
+            bio: body.bio.map(Some),
+            avatar_url: body.avatar_url.map(Some),
+        }
+    }
 }
 
+/// Body of `PATCH /users/me` (RFC 7396): an absent member leaves the field
+/// untouched, an explicit `null` clears it.
+#[derive(serde::Deserialize, Validate, ToSchema)]
+pub struct PatchProfileRequest {
+    #[serde(default, deserialize_with = "nullable")]
+    #[validate(length(min = 2, max = 100))]
+    #[schema(min_length = 2, max_length = 100)]
+    pub display_name: Option<Option<String>>,
+    #[serde(default, deserialize_with = "nullable")]
+    #[validate(length(max = 500))]
+    #[schema(max_length = 500)]
+    pub bio: Option<Option<String>>,
+    #[serde(default, deserialize_with = "nullable")]
+    #[validate(length(max = 2048))]
+    #[schema(max_length = 2048)]
+    pub avatar_url: Option<Option<String>>,
+}
+
+impl From<PatchProfileRequest> for ProfileChanges {
+    fn from(body: PatchProfileRequest) -> Self {
+        ProfileChanges {
+            display_name: body.display_name,
+            bio: body.bio,
+            avatar_url: body.avatar_url,
+        }
+    }
+}
+
+#[derive(serde::Deserialize, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ListProfilesQuery {
+    /// Case-insensitive prefix of `display_name`.
+    #[validate(length(min = 1, max = 100))]
+    pub display_name_prefix: Option<String>,
+    /// Case-insensitive domain of `email`, e.g. `example.com`.
+    #[validate(length(min = 1, max = 255))]
+    pub email_domain: Option<String>,
+}
+
+impl From<ListProfilesQuery> for ProfileFilter {
+    fn from(q: ListProfilesQuery) -> Self {
+        ProfileFilter {
+            display_name_prefix: q.display_name_prefix,
+            email_domain: q.email_domain,
+        }
+    }
+}
+
+#[derive(serde::Deserialize, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SearchProfilesQuery {
+    /// Web-search syntax: `"quoted phrases"`, `or` and `-excluded` terms.
+    #[validate(length(min = 1, max = 200))]
+    #[param(min_length = 1, max_length = 200)]
+    pub q: String,
+}
+
+/// A search match: the profile plus its relevance and highlighted excerpts
+/// (HTML-escaped text with matched terms wrapped in `<mark>`).
+#[derive(Serialize, ToSchema)]
+pub struct ProfileSearchResult {
+    #[serde(flatten)]
+    pub profile: ProfileResponse,
+    pub rank: f32,
+    pub highlights: SearchHighlights,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct SearchHighlights {
+    pub display_name: Option<String>,
+    pub bio: Option<String>,
+}
+
+impl From<ProfileSearchHit> for ProfileSearchResult {
+    fn from(hit: ProfileSearchHit) -> Self {
+        ProfileSearchResult {
+            profile: hit.profile.into(),
+            rank: hit.rank,
+            highlights: SearchHighlights {
+                display_name: hit.display_name_highlight,
+                bio: hit.bio_highlight,
+            },
+        }
+    }
+}
+
+/// Multipart form with the avatar image, its only field.
+#[derive(ToSchema)]
+pub struct AvatarUpload {
+    /// PNG, JPEG, WebP or GIF image.
+    #[schema(value_type = String, format = Binary)]
+    avatar: Vec<u8>,
+    /// Content type of the `avatar` part.
+    #[schema(ignore)]
+    content_type: String,
+}
+
+/// Room in an avatar upload for the multipart boundaries and part headers.
+const MULTIPART_OVERHEAD: usize = 16 * 1024;
+
+pub fn router(config: &Config) -> OpenApiRouter<AppState> {
+    // The image itself is held to uploads.avatar_max_bytes while streaming,
+    // with a clearer error; this bounds the request as a whole.
+    let avatar_body_limit = config.uploads.avatar_max_bytes + MULTIPART_OVERHEAD;
+
+    OpenApiRouter::new()
+        .routes(routes!(list_profiles))
+        .routes(routes!(search_profiles))
+        .routes(routes!(get_me, update_me, patch_me, delete_me))
+        .routes(routes!(upload_avatar).layer(DefaultBodyLimit::max(avatar_body_limit)))
+        .routes(routes!(get_by_id))
+}
+
+/// GET /users?limit=&cursor=&order=&display_name_prefix=&email_domain=
+#[utoipa::path(
+    get,
+    path = "",
+    tag = "users",
+    params(PaginationQuery, ListProfilesQuery),
+    responses(
+        (status = 200, description = "One page of profiles", body = Page<ProfileResponse>),
+        (status = 400, description = "Malformed query or cursor", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+        (status = 422, description = "Invalid query parameters", body = ErrorResponse),
+    )
+)]
+async fn list_profiles(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    pagination: Pagination,
+    ValidatedQuery(query): ValidatedQuery<ListProfilesQuery>,
+) -> Result<Json<Page<ProfileResponse>>, AppError> {
+    let profiles = user_service::list_profiles(
+        state.db.read_for(&auth_user.id),
+        &query.into(),
+        pagination.after()?,
+        pagination.order,
+        pagination.fetch_limit(),
+    )
+    .await?;
+
+    let page = pagination.page(profiles, |p| (p.created_at, p.id));
+
+    Ok(Json(page.map(Into::into)))
+}
+
+/// GET /users/search?q=&limit=&cursor= -- results are ordered by relevance,
+/// so the `order` parameter does not apply.
+#[utoipa::path(
+    get,
+    path = "/search",
+    tag = "users",
+    params(SearchProfilesQuery, PaginationQuery),
+    responses(
+        (status = 200, description = "One page of matches, best first", body = Page<ProfileSearchResult>),
+        (status = 400, description = "Malformed query or cursor", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+        (status = 422, description = "Invalid query parameters", body = ErrorResponse),
+    )
+)]
+async fn search_profiles(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    pagination: Pagination,
+    ValidatedQuery(query): ValidatedQuery<SearchProfilesQuery>,
+) -> Result<Json<Page<ProfileSearchResult>>, AppError> {
+    let hits = user_service::search_profiles(
+        state.db.read_for(&auth_user.id),
+        &query.q,
+        pagination.after()?,
+        pagination.fetch_limit(),
+    )
+    .await?;
+
+    let page = pagination.page(hits, |hit| (hit.rank.to_bits(), hit.profile.id));
+
+    Ok(Json(page.map(Into::into)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/me",
+    tag = "users",
+    params(("If-None-Match" = Option<String>, Header, description = "ETag of a cached copy")),
+    responses(
+        (status = 200, description = "The caller's profile", body = ProfileResponse,
+            headers(("ETag" = String, description = "Current version of the profile"))),
+        (status = 304, description = "The cached copy is current"),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+        (status = 404, description = "No profile yet; call `POST /auth/callback`", body = ErrorResponse),
+    )
+)]
 async fn get_me(
     State(state): State<AppState>,
     auth_user: AuthUser,
-) -> Result<Json<ProfileResponse>, AppError> {
-    let profile = user_service::find_by_auth_id(&state.db, &auth_user.id)
+    preconditions: Preconditions,
+) -> Result<Response, AppError> {
+    let profile = user_service::find_by_auth_id(state.db.read_for(&auth_user.id), &auth_user.id)
         .await?
-        .ok_or_else(|| AppError::NotFound("profile not found".into()))?;
+        .ok_or(AppError::ProfileNotFound)?;
 
-    Ok(Json(profile.into()))
+    Ok(conditional_profile(profile, &preconditions))
 }
 
+/// PUT /users/me -- honours `If-Match` so concurrent editors get 412 instead
+/// of silently overwriting each other.
+#[utoipa::path(
+    put,
+    path = "/me",
+    tag = "users",
+    params(("If-Match" = Option<String>, Header, description = "Update only if the profile still has this ETag")),
+    request_body = UpdateProfileRequest,
+    responses(
+        (status = 200, description = "The updated profile", body = ProfileResponse,
+            headers(("ETag" = String, description = "New version of the profile"))),
+        (status = 400, description = "Malformed JSON or wrong member type", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+        (status = 404, description = "No profile yet", body = ErrorResponse),
+        (status = 412, description = "The profile was modified since the given ETag, or no longer exists", body = ErrorResponse),
+        (status = 415, description = "Not `application/json`", body = ErrorResponse),
+        (status = 422, description = "Invalid body", body = ErrorResponse),
+    )
+)]
 async fn update_me(
     State(state): State<AppState>,
     auth_user: AuthUser,
+    preconditions: Preconditions,
     ValidatedJson(body): ValidatedJson<UpdateProfileRequest>,
-) -> Result<Json<ProfileResponse>, AppError> {
-    let profile = user_service::update_profile(
-        &state.db,
-        &auth_user.id,
-        body.display_name,
-        body.bio,
-        body.avatar_url,
+) -> Result<(ETag, Json<ProfileResponse>), AppError> {
+    let profile = update_own_profile(&state, &auth_user.id, &preconditions, body.into()).await?;
+
+    Ok((ETag::for_profile(&profile), Json(profile.into())))
+}
+
+/// PATCH /users/me -- JSON Merge Patch (`application/merge-patch+json`), so
+/// fields can be cleared with an explicit `null`. Honours `If-Match` like PUT.
+#[utoipa::path(
+    patch,
+    path = "/me",
+    tag = "users",
+    params(("If-Match" = Option<String>, Header, description = "Update only if the profile still has this ETag")),
+    request_body(content = PatchProfileRequest, content_type = MERGE_PATCH_CONTENT_TYPE),
+    responses(
+        (status = 200, description = "The updated profile", body = ProfileResponse,
+            headers(("ETag" = String, description = "New version of the profile"))),
+        (status = 400, description = "Malformed JSON, wrong member type or not an object", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+        (status = 404, description = "No profile yet", body = ErrorResponse),
+        (status = 412, description = "The profile was modified since the given ETag, or no longer exists", body = ErrorResponse),
+        (status = 415, description = "Not `application/merge-patch+json`", body = ErrorResponse),
+        (status = 422, description = "Invalid body", body = ErrorResponse),
+    )
+)]
+async fn patch_me(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    preconditions: Preconditions,
+    MergePatch(body): MergePatch<PatchProfileRequest>,
+) -> Result<(ETag, Json<ProfileResponse>), AppError> {
+    let profile = update_own_profile(&state, &auth_user.id, &preconditions, body.into()).await?;
+
+    Ok((ETag::for_profile(&profile), Json(profile.into())))
+}
+
+/// Applies a PUT or PATCH to the caller's profile in one transaction on the
+/// primary. The row is locked while `If-Match` is checked, so no other write
+/// can land between the check and the update. An uploaded avatar that
+/// `avatar_url` no longer points at is removed once the change is committed.
+async fn update_own_profile(
+    state: &AppState,
+    auth_id: &str,
+    preconditions: &Preconditions,
+    changes: ProfileChanges,
+) -> Result<profile::Model, AppError> {
+    let (previous, updated) = state
+        .db
+        .transaction_for(auth_id, async |txn| {
+            let current = user_service::find_by_auth_id_for_update(txn, auth_id).await?;
+            let expected_version = if_match_version(current.as_ref(), preconditions)?;
+            let updated =
+                user_service::update_profile(txn, auth_id, changes, expected_version).await?;
+            Ok((current, updated))
+        })
+        .await?;
+
+    if let Some(previous) = previous {
+        avatar::remove_replaced(state.storage.as_ref(), &previous, &updated).await;
+    }
+
+    Ok(updated)
+}
+
+/// Evaluates `If-Match` against the caller's current profile and returns the
+/// version the update must be guarded on, if any.
+fn if_match_version(
+    current: Option<&profile::Model>,
+    preconditions: &Preconditions,
+) -> Result<Option<DateTimeWithTimeZone>, AppError> {
+    if !preconditions.has_if_match() {
+        return Ok(None);
+    }
+
+    preconditions.check_if_match(current.map(ETag::for_profile).as_ref())?;
+
+    Ok(current.map(|profile| profile.updated_at))
+}
+
+/// POST /users/me/avatar -- multipart upload with the image in an `avatar`
+/// field (PNG, JPEG, WebP or GIF, at most `uploads.avatar_max_bytes`).
+#[utoipa::path(
+    post,
+    path = "/me/avatar",
+    tag = "users",
+    request_body(content = AvatarUpload, content_type = "multipart/form-data"),
+    responses(
+        (status = 200, description = "The profile with its new `avatar_url`", body = ProfileResponse,
+            headers(("ETag" = String, description = "New version of the profile"))),
+        (status = 400, description = "Missing or unexpected field, or undecodable image", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+        (status = 404, description = "No profile yet", body = ErrorResponse),
+        (status = 413, description = "Image exceeds `uploads.avatar_max_bytes`", body = ErrorResponse),
+        (status = 415, description = "Unsupported image type", body = ErrorResponse),
+    )
+)]
+async fn upload_avatar(
+    State(state): State<AppState>,
+    auth_user: AuthUser,
+    multipart: Multipart,
+) -> Result<(ETag, Json<ProfileResponse>), AppError> {
+    let db = state.db.write_for(&auth_user.id);
+    let profile = user_service::find_by_auth_id(db, &auth_user.id)
+        .await?
+        .ok_or(AppError::ProfileNotFound)?;
+
+    let upload = read_avatar_upload(multipart, state.config.uploads.avatar_max_bytes).await?;
+
+    let profile = avatar::replace_avatar(
+        db,
+        state.storage.as_ref(),
+        profile,
+        &upload.content_type,
+        upload.avatar,
     )
     .await?;
 
-    Ok(Json(profile.into()))
+    Ok((ETag::for_profile(&profile), Json(profile.into())))
+}
+
+/// Reads the `avatar` field, holding it to `max_bytes`. Any other field is
+/// rejected rather than skipped, so nothing unbounded is read.
+async fn read_avatar_upload(
+    Multipart(mut multipart): Multipart,
+    max_bytes: usize,
+) -> Result<AvatarUpload, AppError> {
+    let multipart_error = |e: MultipartError| {
+        if e.status() == StatusCode::PAYLOAD_TOO_LARGE {
+            AppError::PayloadTooLarge(format!("avatar must be at most {max_bytes} bytes"))
+        } else {
+            AppError::BadRequest(e.to_string())
+        }
+    };
+
+    let mut field = match multipart.next_field().await.map_err(multipart_error)? {
+        Some(field) if field.name() == Some("avatar") => field,
+        Some(field) => {
+            return Err(AppError::BadRequest(format!(
+                "unexpected field {:?}; only avatar is accepted",
+                field.name().unwrap_or_default()
+            )));
+        }
+        None => return Err(AppError::BadRequest("missing avatar field".into())),
+    };
+
+    let content_type = field
+        .content_type()
+        .map(str::to_owned)
+        .ok_or_else(|| AppError::UnsupportedMediaType("avatar content type is missing".into()))?;
+
+    let mut avatar = Vec::new();
+    while let Some(chunk) = field.chunk().await.map_err(multipart_error)? {
+        if avatar.len() + chunk.len() > max_bytes {
+            return Err(AppError::PayloadTooLarge(format!(
+                "avatar must be at most {max_bytes} bytes"
+            )));
+        }
+        avatar.extend_from_slice(&chunk);
+    }
+    drop(field);
+
+    if multipart
+        .next_field()
+        .await
+        .map_err(multipart_error)?
+        .is_some()
+    {
+        return Err(AppError::BadRequest(
+            "unexpected field after avatar; only avatar is accepted".into(),
+        ));
+    }
+
+    Ok(AvatarUpload {
+        avatar,
+        content_type,
+    })
 }
 
+#[utoipa::path(
+    delete,
+    path = "/me",
+    tag = "users",
+    responses(
+        (status = 204, description = "Profile soft-deleted"),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+        (status = 404, description = "No profile", body = ErrorResponse),
+    )
+)]
 async fn delete_me(
     State(state): State<AppState>,
     auth_user: AuthUser,
 ) -> Result<axum::http::StatusCode, AppError> {
-    user_service::delete_profile(&state.db, &auth_user.id).await?;
+    user_service::delete_profile(
+        state.db.write_for(&auth_user.id),
+        &auth_user.id,
+        &auth_user.id,
+    )
+    .await?;
     Ok(axum::http::StatusCode::NO_CONTENT)
 }
 
+#[utoipa::path(
+    get,
+    path = "/{id}",
+    tag = "users",
+    params(
+        ("id" = Uuid, Path, description = "Profile id"),
+        ("If-None-Match" = Option<String>, Header, description = "ETag of a cached copy"),
+    ),
+    responses(
+        (status = 200, description = "The profile", body = ProfileResponse,
+            headers(("ETag" = String, description = "Current version of the profile"))),
+        (status = 304, description = "The cached copy is current"),
+        (status = 400, description = "Malformed id", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid token", body = ErrorResponse),
+        (status = 404, description = "No such profile", body = ErrorResponse),
+    )
+)]
 async fn get_by_id(
     State(state): State<AppState>,
-    _auth_user: AuthUser,
+    auth_user: AuthUser,
+    preconditions: Preconditions,
     Path(id): Path<Uuid>,
-) -> Result<Json<ProfileResponse>, AppError> {
-    let profile = user_service::find_by_id(&state.db, id)
+) -> Result<Response, AppError> {
+    let profile = user_service::find_by_id(state.db.read_for(&auth_user.id), id)
         .await?
-        .ok_or_else(|| AppError::NotFound("profile not found".into()))?;
+        .ok_or(AppError::ProfileNotFound)?;
+
+    Ok(conditional_profile(profile, &preconditions))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::body::Body;
+    use axum::extract::{FromRequest, FromRequestParts};
+    use axum::http::{HeaderValue, Request, header};
+    use axum::response::IntoResponse;
+    use sea_orm::EntityTrait;
+
+    use super::*;
+    use crate::db;
+    use crate::services::storage::{LocalStorage, Storage};
+
+    const BOUNDARY: &str = "X-BOUNDARY";
+
+    /// Builds a multipart body from `(name, content type, bytes)` parts.
+    async fn multipart(parts: &[(&str, &str, &[u8])]) -> Multipart {
+        let mut body = Vec::new();
+        for (name, content_type, bytes) in parts {
+            body.extend_from_slice(
+                format!(
+                    "--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"{name}\"; \
+                     filename=\"f\"\r\nContent-Type: {content_type}\r\n\r\n"
+                )
+                .as_bytes(),
+            );
+            body.extend_from_slice(bytes);
+            body.extend_from_slice(b"\r\n");
+        }
+        body.extend_from_slice(format!("--{BOUNDARY}--\r\n").as_bytes());
+
+        let request = Request::builder()
+            .header(
+                header::CONTENT_TYPE,
+                format!("multipart/form-data; boundary={BOUNDARY}"),
+            )
+            .body(Body::from(body))
+            .unwrap();
+        Multipart::from_request(request, &()).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn reads_the_avatar_field() {
+        let form = multipart(&[("avatar", "image/png", b"png bytes")]).await;
+
+        let upload = read_avatar_upload(form, 100).await.unwrap();
+
+        assert_eq!(upload.avatar, b"png bytes");
+        assert_eq!(upload.content_type, "image/png");
+    }
+
+    #[tokio::test]
+    async fn rejects_fields_before_or_after_the_avatar() {
+        let before = multipart(&[("note", "text/plain", b"hi"), ("avatar", "image/png", b"x")]);
+        let after = multipart(&[("avatar", "image/png", b"x"), ("note", "text/plain", b"hi")]);
+
+        for form in [before.await, after.await] {
+            let result = read_avatar_upload(form, 100).await;
+            assert!(matches!(result, Err(AppError::BadRequest(_))));
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_missing_avatar() {
+        let result = read_avatar_upload(multipart(&[]).await, 100).await;
+
+        assert!(matches!(result, Err(AppError::BadRequest(_))));
+    }
+
+    #[tokio::test]
+    async fn rejects_avatar_over_the_limit() {
+        let form = multipart(&[("avatar", "image/png", &[0; 101])]).await;
+
+        let result = read_avatar_upload(form, 100).await;
+
+        assert!(matches!(result, Err(AppError::PayloadTooLarge(_))));
+    }
+
+    async fn preconditions(if_match: &HeaderValue) -> Preconditions {
+        let (mut parts, ()) = Request::builder()
+            .header(header::IF_MATCH, if_match)
+            .body(())
+            .unwrap()
+            .into_parts();
+        Preconditions::from_request_parts(&mut parts, &())
+            .await
+            .unwrap()
+    }
+
+    fn rename(name: &str) -> ProfileChanges {
+        ProfileChanges {
+            display_name: Some(Some(name.into())),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn update_checks_if_match_against_the_stored_version() {
+        let db = db::test_database().await;
+        let state = AppState {
+            db: db::Pools::for_tests(db.clone()),
+            ..AppState::for_tests()
+        };
+        let auth_id = format!("test-{}", Uuid::new_v4());
+        let created = user_service::create_profile(&db, auth_id.clone(), "a@example.com".into())
+            .await
+            .unwrap();
+        let response = (ETag::for_profile(&created), ()).into_response();
+        let original = &response.headers()[header::ETAG];
+
+        let first = update_own_profile(
+            &state,
+            &auth_id,
+            &preconditions(original).await,
+            rename("First"),
+        )
+        .await;
+        let stale = update_own_profile(
+            &state,
+            &auth_id,
+            &preconditions(original).await,
+            rename("Second"),
+        )
+        .await;
+        let stored = user_service::find_by_auth_id(&db, &auth_id).await.unwrap();
+
+        profile::Entity::delete_by_id(created.id)
+            .exec(&db)
+            .await
+            .unwrap();
+        assert_eq!(first.unwrap().display_name.as_deref(), Some("First"));
+        assert!(matches!(stale, Err(AppError::PreconditionFailed(_))));
+        assert_eq!(stored.unwrap().display_name.as_deref(), Some("First"));
+    }
+
+    #[tokio::test]
+    async fn clearing_avatar_url_removes_the_uploaded_avatar() {
+        let db = db::test_database().await;
+        let root = std::env::temp_dir().join(format!("rest-api-{}-clear", std::process::id()));
+        let storage = LocalStorage::new(&root, "https://api.example.com/uploads");
+        let auth_id = format!("test-{}", Uuid::new_v4());
+        let created = user_service::create_profile(&db, auth_id.clone(), "a@example.com".into())
+            .await
+            .unwrap();
+        let key = format!("avatars/{}/1/512.png", created.id);
+        let url = storage
+            .put(&key, b"png".to_vec(), "image/png")
+            .await
+            .unwrap();
+        let state = AppState {
+            db: db::Pools::for_tests(db.clone()),
+            storage: Arc::new(storage),
+            ..AppState::for_tests()
+        };
+        let avatar = |url: Option<String>| ProfileChanges {
+            avatar_url: Some(url),
+            ..Default::default()
+        };
+        let (mut parts, ()) = Request::new(()).into_parts();
+        let unconditional = Preconditions::from_request_parts(&mut parts, &())
+            .await
+            .unwrap();
+
+        let set = update_own_profile(&state, &auth_id, &unconditional, avatar(Some(url))).await;
+        let kept = root.join(&key).exists();
+        let cleared = update_own_profile(&state, &auth_id, &unconditional, avatar(None)).await;
+
+        profile::Entity::delete_by_id(created.id)
+            .exec(&db)
+            .await
+            .unwrap();
+        set.unwrap();
+        cleared.unwrap();
+        assert!(kept);
+        assert!(!root.join(&key).exists());
 
-    Ok(Json(profile.into()))
+        tokio::fs::remove_dir_all(&root).await.unwrap();
+    }
 }
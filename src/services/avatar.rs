@@ -0,0 +1,284 @@
+use std::io::Cursor;
+
+use image::imageops::FilterType;
+use image::{ImageFormat, ImageReader, Limits};
+use sea_orm::TransactionTrait;
+use uuid::Uuid;
+
+use crate::errors::AppError;
+use crate::models::profile;
+use crate::services::storage::Storage;
+use crate::services::user::{self as user_service, ProfileChanges};
+
+/// Square renditions generated for every upload. The first one is the URL
+/// stored in `avatar_url`; the others sit next to it with their size as the
+/// file name (e.g. `.../128.png`).
+const AVATAR_SIZES: [u32; 2] = [512, 128];
+
+/// Upload content types we accept and the decoder used for each.
+const ACCEPTED_TYPES: [(&str, ImageFormat); 4] = [
+    ("image/png", ImageFormat::Png),
+    ("image/jpeg", ImageFormat::Jpeg),
+    ("image/webp", ImageFormat::WebP),
+    ("image/gif", ImageFormat::Gif),
+];
+
+/// Larger images are rejected before decoding, so a small file cannot
+/// expand into a huge bitmap. Avatars are at most 512 px, so this is plenty.
+const MAX_DIMENSION: u32 = 4096;
+
+/// Cap on what the decoder may allocate, about one 4096×4096 RGBA image.
+const MAX_DECODE_BYTES: u64 = 64 * 1024 * 1024;
+
+/// Storage prefix holding every avatar upload of a profile.
+fn profile_prefix(profile_id: Uuid) -> String {
+    format!("avatars/{profile_id}/")
+}
+
+/// Decodes and resizes an uploaded image, stores every rendition, points the
+/// profile's `avatar_url` at the largest one and removes the avatar it
+/// replaces.
+pub async fn replace_avatar(
+    db: &impl TransactionTrait,
+    storage: &dyn Storage,
+    profile: profile::Model,
+    content_type: &str,
+    bytes: Vec<u8>,
+) -> Result<profile::Model, AppError> {
+    let format = ACCEPTED_TYPES
+        .iter()
+        .find(|(accepted, _)| *accepted == content_type)
+        .map(|(_, format)| *format)
+        .ok_or_else(|| {
+            AppError::UnsupportedMediaType(format!(
+                "avatar must be one of: {}",
+                ACCEPTED_TYPES.map(|(t, _)| t).join(", ")
+            ))
+        })?;
+
+    let renditions = tokio::task::spawn_blocking(move || render(&bytes, format))
+        .await
+        .map_err(|e| AppError::Internal(format!("avatar processing failed: {e}")))??;
+
+    let upload_prefix = format!("{}{}/", profile_prefix(profile.id), Uuid::new_v4());
+    let mut avatar_url = None;
+
+    for (size, png) in renditions {
+        let url = storage
+            .put(&format!("{upload_prefix}{size}.png"), png, "image/png")
+            .await?;
+        avatar_url.get_or_insert(url);
+    }
+
+    let changes = ProfileChanges {
+        avatar_url: Some(avatar_url),
+        ..Default::default()
+    };
+
+    // The row stays locked from reading the avatar being replaced until the
+    // new one is committed. Concurrent uploads thus take turns, and each
+    // removes the avatar it actually replaced, so none is left orphaned.
+    let switched = async {
+        let txn = db.begin().await?;
+        let previous = user_service::find_by_auth_id_for_update(&txn, &profile.auth_id)
+            .await?
+            .ok_or(AppError::ProfileNotFound)?;
+        let updated = user_service::update_profile(&txn, &profile.auth_id, changes, None).await?;
+        txn.commit().await?;
+        Ok::<_, AppError>((previous, updated))
+    }
+    .await;
+
+    let (previous, updated) = match switched {
+        Ok(switched) => switched,
+        Err(e) => {
+            remove_upload(storage, &upload_prefix).await;
+            return Err(e);
+        }
+    };
+
+    remove_replaced(storage, &previous, &updated).await;
+
+    Ok(updated)
+}
+
+/// Deletes the stored upload behind `previous.avatar_url` once the profile
+/// points somewhere else. External URLs are left alone.
+pub async fn remove_replaced(
+    storage: &dyn Storage,
+    previous: &profile::Model,
+    current: &profile::Model,
+) {
+    let Some(old_url) = &previous.avatar_url else {
+        return;
+    };
+    if current.avatar_url.as_ref() == Some(old_url) {
+        return;
+    }
+
+    let prefix = profile_prefix(previous.id);
+    let upload = storage
+        .key_for_url(old_url)
+        .filter(|key| key.starts_with(&prefix))
+        .and_then(|key| key.rsplit_once('/').map(|(dir, _)| format!("{dir}/")));
+
+    if let Some(upload) = upload {
+        remove_upload(storage, &upload).await;
+    }
+}
+
+/// Deletes every avatar ever uploaded for a profile. Used when the profile
+/// itself is permanently removed.
+pub async fn remove_all(storage: &dyn Storage, profile_id: Uuid) {
+    remove_upload(storage, &profile_prefix(profile_id)).await;
+}
+
+/// Cleanup is best effort: a leftover file is not worth failing the request.
+async fn remove_upload(storage: &dyn Storage, prefix: &str) {
+    if let Err(e) = storage.delete_prefix(prefix).await {
+        tracing::warn!("Failed to remove avatar files under {prefix}: {e}");
+    }
+}
+
+fn render(bytes: &[u8], format: ImageFormat) -> Result<Vec<(u32, Vec<u8>)>, AppError> {
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_DIMENSION);
+    limits.max_image_height = Some(MAX_DIMENSION);
+    limits.max_alloc = Some(MAX_DECODE_BYTES);
+
+    let mut reader = ImageReader::with_format(Cursor::new(bytes), format);
+    reader.limits(limits);
+
+    let image = reader
+        .decode()
+        .map_err(|e| AppError::BadRequest(format!("invalid image: {e}")))?;
+
+    AVATAR_SIZES
+        .iter()
+        .map(|&size| {
+            let mut png = Vec::new();
+            image
+                .resize_to_fill(size, size, FilterType::Lanczos3)
+                .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+                .map_err(|e| AppError::Internal(format!("avatar encoding failed: {e}")))?;
+            Ok((size, png))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use image::RgbImage;
+    use sea_orm::DatabaseConnection;
+
+    use super::*;
+    use crate::services::storage::LocalStorage;
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        RgbImage::new(width, height)
+            .write_to(&mut Cursor::new(&mut bytes), ImageFormat::Png)
+            .unwrap();
+        bytes
+    }
+
+    fn profile(id: Uuid, avatar_url: Option<&str>) -> profile::Model {
+        let now = chrono::Utc::now().fixed_offset();
+        profile::Model {
+            id,
+            auth_id: "user-1".into(),
+            display_name: None,
+            email: "user@example.com".into(),
+            avatar_url: avatar_url.map(str::to_owned),
+            bio: None,
+            created_at: now,
+            updated_at: now,
+            deleted_at: None,
+            deleted_by: None,
+        }
+    }
+
+    /// Local storage in a directory unique to the calling test.
+    fn storage(name: &str) -> (LocalStorage, PathBuf) {
+        let root = std::env::temp_dir().join(format!("rest-api-{}-{name}", std::process::id()));
+        let storage = LocalStorage::new(&root, "https://api.example.com/uploads");
+        (storage, root)
+    }
+
+    async fn upload(storage: &LocalStorage, key: &str) -> String {
+        storage.put(key, png(1, 1), "image/png").await.unwrap()
+    }
+
+    #[test]
+    fn render_crops_to_every_size() {
+        let renditions = render(&png(300, 200), ImageFormat::Png).unwrap();
+
+        let sizes: Vec<_> = renditions
+            .iter()
+            .map(|(size, bytes)| {
+                let image = image::load_from_memory_with_format(bytes, ImageFormat::Png).unwrap();
+                (*size, image.width(), image.height())
+            })
+            .collect();
+        assert_eq!(sizes, [(512, 512, 512), (128, 128, 128)]);
+    }
+
+    #[test]
+    fn render_rejects_oversized_and_undecodable_images() {
+        let too_wide = render(&png(MAX_DIMENSION + 1, 1), ImageFormat::Png);
+        let not_an_image = render(b"not an image", ImageFormat::Png);
+        let wrong_format = render(&png(8, 8), ImageFormat::Jpeg);
+
+        for result in [too_wide, not_an_image, wrong_format] {
+            assert!(matches!(result, Err(AppError::BadRequest(_))));
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_unsupported_content_types() {
+        let (storage, _) = storage("unsupported");
+
+        let result = replace_avatar(
+            &DatabaseConnection::Disconnected,
+            &storage,
+            profile(Uuid::new_v4(), None),
+            "image/svg+xml",
+            b"<svg/>".to_vec(),
+        )
+        .await;
+
+        assert!(matches!(result, Err(AppError::UnsupportedMediaType(_))));
+    }
+
+    #[tokio::test]
+    async fn remove_replaced_deletes_only_the_replaced_upload() {
+        let (storage, root) = storage("replaced");
+        let id = Uuid::new_v4();
+        let other = Uuid::new_v4();
+        let old = upload(&storage, &format!("avatars/{id}/1/512.png")).await;
+        let new = upload(&storage, &format!("avatars/{id}/2/512.png")).await;
+        let foreign = upload(&storage, &format!("avatars/{other}/1/512.png")).await;
+
+        // Unchanged, external and other profiles' avatars are left alone.
+        remove_replaced(&storage, &profile(id, Some(&new)), &profile(id, Some(&new))).await;
+        remove_replaced(
+            &storage,
+            &profile(id, Some("https://cdn.example.com/a.png")),
+            &profile(id, None),
+        )
+        .await;
+        remove_replaced(&storage, &profile(id, Some(&foreign)), &profile(id, None)).await;
+        remove_replaced(&storage, &profile(id, Some(&old)), &profile(id, Some(&new))).await;
+
+        assert!(!root.join(format!("avatars/{id}/1")).exists());
+        assert!(root.join(format!("avatars/{id}/2/512.png")).exists());
+        assert!(root.join(format!("avatars/{other}/1/512.png")).exists());
+
+        remove_replaced(&storage, &profile(id, Some(&new)), &profile(id, None)).await;
+        assert!(!root.join(format!("avatars/{id}/2")).exists());
+
+        tokio::fs::remove_dir_all(&root).await.unwrap();
+    }
+}
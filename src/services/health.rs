@@ -0,0 +1,32 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use sea_orm::{ConnectionTrait, DatabaseConnection, DbBackend, DbErr, Statement};
+
+/// Set once shutdown begins. Readiness then fails immediately so load
+/// balancers stop routing new traffic while in-flight requests finish.
+/// Cheap to clone; all clones share one flag.
+#[derive(Clone, Default)]
+pub struct Draining(Arc<AtomicBool>);
+
+impl Draining {
+    pub fn start(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Round trip to the database. Returns the `statement_timeout` the pooled
+/// connection actually runs with, confirming the configured one applied.
+pub async fn ping_database(db: &DatabaseConnection) -> Result<String, DbErr> {
+    db.query_one(Statement::from_string(
+        DbBackend::Postgres,
+        "SHOW statement_timeout",
+    ))
+    .await?
+    .ok_or_else(|| DbErr::Custom("SHOW returned no row".into()))?
+    .try_get_by_index(0)
+}
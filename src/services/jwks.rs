@@ -0,0 +1,263 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use jsonwebtoken::jwk::{AlgorithmParameters, EllipticCurve, Jwk, PublicKeyUse};
+use jsonwebtoken::{Algorithm, DecodingKey};
+use serde::Deserialize;
+use tokio::sync::{Mutex, RwLock};
+
+use crate::config::AuthConfig;
+use crate::errors::AppError;
+
+/// Minimum time between two fetches of the key set. Stops clients sending
+/// made-up `kid`s (or an unreachable JWKS endpoint) from triggering a fetch
+/// on every request.
+const MIN_REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Limit on one fetch of the key set. Kept below the readiness probe's check
+/// timeout so a slow endpoint fails the fetch instead of the probe cancelling
+/// it.
+pub const FETCH_TIMEOUT: Duration = Duration::from_millis(1500);
+
+/// A public key from the JWKS together with the algorithm it verifies.
+#[derive(Clone)]
+pub struct SigningKey {
+    pub key: DecodingKey,
+    pub algorithm: Algorithm,
+}
+
+#[derive(Default)]
+struct KeySet {
+    keys: HashMap<String, SigningKey>,
+    fetched_at: Option<Instant>,
+    attempted_at: Option<Instant>,
+}
+
+impl KeySet {
+    fn is_fresh(&self, ttl: Duration) -> bool {
+        self.fetched_at.is_some_and(|at| at.elapsed() < ttl)
+    }
+}
+
+#[derive(Deserialize)]
+struct JwksDocument {
+    keys: Vec<serde_json::Value>,
+}
+
+/// Fetches and caches the JSON Web Key Set Supabase uses to sign asymmetric
+/// (RS256/ES256) access tokens. Cheap to clone; all clones share one cache.
+#[derive(Clone)]
+pub struct JwksCache {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    url: String,
+    ttl: Duration,
+    http: reqwest::Client,
+    keys: RwLock<KeySet>,
+    refresh: Mutex<()>,
+}
+
+impl JwksCache {
+    pub fn new(config: &AuthConfig) -> Self {
+        let http = reqwest::Client::builder()
+            .timeout(FETCH_TIMEOUT)
+            .build()
+            .expect("Failed to build HTTP client");
+
+        JwksCache {
+            inner: Arc::new(Inner {
+                url: config.jwks_url(),
+                ttl: Duration::from_secs(config.jwks_ttl_secs),
+                http,
+                keys: RwLock::new(KeySet::default()),
+                refresh: Mutex::new(()),
+            }),
+        }
+    }
+
+    /// Returns the signing key identified by `kid`, refreshing the cached set
+    /// when it has expired or does not contain the requested key.
+    pub async fn key(&self, kid: &str) -> Result<SigningKey, AppError> {
+        {
+            let cached = self.inner.keys.read().await;
+            if cached.is_fresh(self.inner.ttl)
+                && let Some(key) = cached.keys.get(kid)
+            {
+                return Ok(key.clone());
+            }
+        }
+
+        self.refresh(Some(kid)).await;
+
+        let cached = self.inner.keys.read().await;
+        match cached.keys.get(kid) {
+            Some(key) => Ok(key.clone()),
+            None if cached.fetched_at.is_none() => {
+                Err(AppError::Internal("signing keys are unavailable".into()))
+            }
+            None => Err(AppError::InvalidToken("unknown signing key".into())),
+        }
+    }
+
+    /// Number of usable signing keys, fetching the set first if it has never
+    /// been loaded or has expired. Zero means asymmetric tokens cannot be
+    /// verified.
+    pub async fn key_count(&self) -> usize {
+        self.refresh(None).await;
+        self.inner.keys.read().await.keys.len()
+    }
+
+    /// Fetches the key set when it has expired or lacks `kid`, unless a fetch
+    /// was attempted within `MIN_REFRESH_INTERVAL`.
+    async fn refresh(&self, kid: Option<&str>) {
+        // Serialise refreshes so a burst of requests results in one fetch.
+        let _guard = self.inner.refresh.lock().await;
+
+        {
+            let mut cached = self.inner.keys.write().await;
+            let recently_attempted = cached
+                .attempted_at
+                .is_some_and(|at| at.elapsed() < MIN_REFRESH_INTERVAL);
+            let needs_refresh = !cached.is_fresh(self.inner.ttl)
+                || kid.is_some_and(|kid| !cached.keys.contains_key(kid));
+
+            if recently_attempted || !needs_refresh {
+                return;
+            }
+            // Recorded before fetching, so a caller that gives up waiting
+            // (a timed-out probe) still holds off the next attempt.
+            cached.attempted_at = Some(Instant::now());
+        }
+
+        let result = self.fetch().await;
+        let mut cached = self.inner.keys.write().await;
+
+        match result {
+            Ok(keys) => {
+                tracing::info!("Loaded {} signing keys from JWKS", keys.len());
+                cached.keys = keys;
+                cached.fetched_at = Some(Instant::now());
+            }
+            // Keep serving the previous keys; they are usually still valid.
+            Err(e) => tracing::warn!("Failed to refresh JWKS from {}: {e}", self.inner.url),
+        }
+    }
+
+    async fn fetch(&self) -> Result<HashMap<String, SigningKey>, reqwest::Error> {
+        let document: JwksDocument = self
+            .inner
+            .http
+            .get(&self.inner.url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        // Parse keys one by one so a single unsupported entry does not
+        // invalidate the whole set.
+        Ok(document
+            .keys
+            .into_iter()
+            .filter_map(|value| serde_json::from_value::<Jwk>(value).ok())
+            .filter_map(|jwk| signing_key(&jwk))
+            .collect())
+    }
+}
+
+/// Converts a published JWK into a verification key. Symmetric keys and keys
+/// not meant for signatures are skipped: accepting them would allow
+/// algorithm-confusion attacks.
+fn signing_key(jwk: &Jwk) -> Option<(String, SigningKey)> {
+    if matches!(jwk.common.public_key_use, Some(PublicKeyUse::Encryption)) {
+        return None;
+    }
+
+    let algorithm = match (&jwk.algorithm, jwk.common.key_algorithm) {
+        (AlgorithmParameters::OctetKey(_), _) => return None,
+        (_, Some(alg)) => Algorithm::from_str(&alg.to_string()).ok()?,
+        (AlgorithmParameters::RSA(_), None) => Algorithm::RS256,
+        (AlgorithmParameters::EllipticCurve(params), None)
+            if params.curve == EllipticCurve::P256 =>
+        {
+            Algorithm::ES256
+        }
+        _ => return None,
+    };
+
+    if matches!(
+        algorithm,
+        Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+    ) {
+        return None;
+    }
+
+    let kid = jwk.common.key_id.clone()?;
+    let key = DecodingKey::from_jwk(jwk).ok()?;
+
+    Some((kid, SigningKey { key, algorithm }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use axum::Json;
+    use axum::routing::get;
+    use serde_json::json;
+
+    use super::*;
+
+    /// A JWKS endpoint that answers after `delay`, and the number of fetches
+    /// it has received.
+    async fn serve_jwks(delay: Duration) -> (JwksCache, Arc<AtomicUsize>) {
+        let fetches = Arc::new(AtomicUsize::new(0));
+        let counter = fetches.clone();
+        let app = axum::Router::new().route(
+            "/auth/v1/.well-known/jwks.json",
+            get(move || async move {
+                counter.fetch_add(1, Ordering::SeqCst);
+                tokio::time::sleep(delay).await;
+                Json(json!({ "keys": [] }))
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let config = AuthConfig {
+            supabase_url: format!("http://{addr}"),
+            jwt_secret: None,
+            jwks_ttl_secs: 600,
+        };
+        (JwksCache::new(&config), fetches)
+    }
+
+    #[tokio::test]
+    async fn a_cancelled_fetch_still_backs_off() {
+        let (jwks, fetches) = serve_jwks(Duration::from_secs(5)).await;
+
+        let cancelled = tokio::time::timeout(Duration::from_millis(200), jwks.key_count()).await;
+        assert!(cancelled.is_err());
+
+        let started = Instant::now();
+        assert_eq!(jwks.key_count().await, 0);
+        assert!(started.elapsed() < Duration::from_millis(200));
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn a_slow_endpoint_fails_within_the_fetch_timeout() {
+        let (jwks, fetches) = serve_jwks(Duration::from_secs(5)).await;
+
+        let started = Instant::now();
+        assert_eq!(jwks.key_count().await, 0);
+        assert!(started.elapsed() < FETCH_TIMEOUT + Duration::from_millis(500));
+        assert!(matches!(jwks.key("k1").await, Err(AppError::Internal(_))));
+        assert_eq!(fetches.load(Ordering::SeqCst), 1);
+    }
+}
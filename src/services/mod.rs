@@ -1 +1,5 @@
+pub mod avatar;
+pub mod health;
+pub mod jwks;
+pub mod storage;
 pub mod user;
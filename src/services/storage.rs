@@ -0,0 +1,180 @@
+use std::path::{Component, Path, PathBuf};
+
+use async_trait::async_trait;
+
+use crate::errors::AppError;
+
+/// Backend for user-uploaded files. Keys are `/`-separated relative paths
+/// such as `avatars/{profile_id}/{upload_id}/512.png`. The local filesystem
+/// implementation comes first; an S3-compatible one can implement the same
+/// trait.
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// Stores `bytes` under `key`, replacing any existing object, and returns
+    /// the public URL clients should use to fetch it.
+    async fn put(&self, key: &str, bytes: Vec<u8>, content_type: &str) -> Result<String, AppError>;
+
+    /// Removes every object under `prefix`, which must end with `/`.
+    /// Missing prefixes are not an error.
+    async fn delete_prefix(&self, prefix: &str) -> Result<(), AppError>;
+
+    /// Maps a URL returned by `put` back to its key. Returns `None` for URLs
+    /// this backend did not produce, such as external avatar links.
+    fn key_for_url(&self, url: &str) -> Option<String>;
+}
+
+/// Stores files under a local directory, served by the app at `public_url`.
+pub struct LocalStorage {
+    root: PathBuf,
+    public_url: String,
+}
+
+impl LocalStorage {
+    pub fn new(root: impl Into<PathBuf>, public_url: &str) -> Self {
+        LocalStorage {
+            root: root.into(),
+            public_url: public_url.trim_end_matches('/').to_string(),
+        }
+    }
+
+    /// Resolves `key` inside the root, refusing anything that could escape it.
+    fn path_for(&self, key: &str) -> Result<PathBuf, AppError> {
+        let relative = Path::new(key.trim_end_matches('/'));
+        let is_plain = relative
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)));
+
+        if key.is_empty() || !is_plain {
+            return Err(AppError::Internal(format!("invalid storage key: {key}")));
+        }
+
+        Ok(self.root.join(relative))
+    }
+}
+
+#[async_trait]
+impl Storage for LocalStorage {
+    async fn put(
+        &self,
+        key: &str,
+        bytes: Vec<u8>,
+        _content_type: &str,
+    ) -> Result<String, AppError> {
+        let path = self.path_for(key)?;
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .map_err(storage_error)?;
+        }
+
+        // Write to a temporary file first so readers never see partial data.
+        let tmp = path.with_extension("tmp");
+        tokio::fs::write(&tmp, bytes).await.map_err(storage_error)?;
+        tokio::fs::rename(&tmp, &path)
+            .await
+            .map_err(storage_error)?;
+
+        Ok(format!("{}/{key}", self.public_url))
+    }
+
+    async fn delete_prefix(&self, prefix: &str) -> Result<(), AppError> {
+        if !prefix.ends_with('/') {
+            return Err(AppError::Internal(format!(
+                "storage prefix must end with '/': {prefix}"
+            )));
+        }
+
+        match tokio::fs::remove_dir_all(self.path_for(prefix)?).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(storage_error(e)),
+            _ => Ok(()),
+        }
+    }
+
+    fn key_for_url(&self, url: &str) -> Option<String> {
+        url.strip_prefix(&self.public_url)?
+            .strip_prefix('/')
+            .map(str::to_owned)
+    }
+}
+
+fn storage_error(e: std::io::Error) -> AppError {
+    AppError::Internal(format!("storage error: {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Storage rooted in a directory unique to the calling test.
+    fn storage(name: &str) -> LocalStorage {
+        let root = std::env::temp_dir().join(format!("rest-api-{}-{name}", std::process::id()));
+        LocalStorage::new(root, "https://api.example.com/uploads/")
+    }
+
+    async fn fs_read(storage: &LocalStorage, key: &str) -> Option<Vec<u8>> {
+        tokio::fs::read(storage.path_for(key).unwrap()).await.ok()
+    }
+
+    #[test]
+    fn path_for_stays_inside_the_root() {
+        let storage = storage("paths");
+
+        assert_eq!(
+            storage.path_for("avatars/a/512.png").unwrap(),
+            storage.root.join("avatars/a/512.png")
+        );
+        for key in [
+            "",
+            "/etc/passwd",
+            "../secret",
+            "avatars/../../secret",
+            "./avatars",
+        ] {
+            assert!(storage.path_for(key).is_err(), "{key}");
+        }
+    }
+
+    #[test]
+    fn key_for_url_only_maps_its_own_urls() {
+        let storage = storage("urls");
+
+        assert_eq!(
+            storage.key_for_url("https://api.example.com/uploads/avatars/a/512.png"),
+            Some("avatars/a/512.png".into())
+        );
+        assert_eq!(storage.key_for_url("https://cdn.example.com/a.png"), None);
+        assert_eq!(
+            storage.key_for_url("https://api.example.com/uploadsx/a.png"),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn put_then_delete_prefix() {
+        let storage = storage("put");
+
+        let url = storage
+            .put("avatars/a/1/512.png", b"png".to_vec(), "image/png")
+            .await
+            .unwrap();
+        storage
+            .put("avatars/a/2/512.png", b"png".to_vec(), "image/png")
+            .await
+            .unwrap();
+
+        assert_eq!(url, "https://api.example.com/uploads/avatars/a/1/512.png");
+        assert_eq!(
+            fs_read(&storage, "avatars/a/1/512.png").await,
+            Some(b"png".to_vec())
+        );
+
+        storage.delete_prefix("avatars/a/1/").await.unwrap();
+        storage.delete_prefix("avatars/missing/").await.unwrap();
+
+        assert_eq!(fs_read(&storage, "avatars/a/1/512.png").await, None);
+        assert!(fs_read(&storage, "avatars/a/2/512.png").await.is_some());
+        assert!(storage.delete_prefix("avatars/a").await.is_err());
+
+        tokio::fs::remove_dir_all(&storage.root).await.unwrap();
+    }
+}
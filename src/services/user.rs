@@ -1,31 +1,265 @@
-use sea_orm::{ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, Set};
+use sea_orm::prelude::DateTimeWithTimeZone;
+use sea_orm::sea_query::{Expr, Func, LikeExpr, OnConflict};
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, Condition, ConnectionTrait, DatabaseTransaction, DbBackend,
+    DbErr, EntityTrait, FromQueryResult, IdenStatic, Iterable, Order, QueryFilter, QueryOrder,
+    QuerySelect, Select, Set, Statement, Value,
+};
 use uuid::Uuid;
 
 use crate::errors::AppError;
+use crate::extractors::pagination::SortOrder;
 use crate::models::profile;
+use crate::monitoring::QueryTimer;
+use crate::services::avatar;
+use crate::services::storage::Storage;
 
+/// Profiles that have not been soft-deleted. Every lookup starts from here so
+/// deleted accounts stay invisible until they are restored or purged.
+fn active_profiles() -> Select<profile::Entity> {
+    profile::Entity::find().filter(profile::Column::DeletedAt.is_null())
+}
+
+#[tracing::instrument(skip(db))]
 pub async fn find_by_auth_id(
-    db: &DatabaseConnection,
+    db: &impl ConnectionTrait,
     auth_id: &str,
 ) -> Result<Option<profile::Model>, AppError> {
-    Ok(profile::Entity::find()
+    let _timer = QueryTimer::start("find_by_auth_id");
+    Ok(active_profiles()
         .filter(profile::Column::AuthId.eq(auth_id))
         .one(db)
         .await?)
 }
 
+/// Like `find_by_auth_id`, but locks the row until the transaction ends, so
+/// writers that read the profile before changing it take turns.
+#[tracing::instrument(skip(txn))]
+pub async fn find_by_auth_id_for_update(
+    txn: &DatabaseTransaction,
+    auth_id: &str,
+) -> Result<Option<profile::Model>, AppError> {
+    let _timer = QueryTimer::start("find_by_auth_id_for_update");
+    Ok(active_profiles()
+        .filter(profile::Column::AuthId.eq(auth_id))
+        .lock_exclusive()
+        .one(txn)
+        .await?)
+}
+
+#[tracing::instrument(skip(db))]
 pub async fn find_by_id(
-    db: &DatabaseConnection,
+    db: &impl ConnectionTrait,
     id: Uuid,
 ) -> Result<Option<profile::Model>, AppError> {
-    Ok(profile::Entity::find_by_id(id).one(db).await?)
+    let _timer = QueryTimer::start("find_by_id");
+    Ok(active_profiles()
+        .filter(profile::Column::Id.eq(id))
+        .one(db)
+        .await?)
+}
+
+/// A full-text search match with its relevance and highlighted excerpts.
+#[derive(FromQueryResult)]
+pub struct ProfileSearchHit {
+    #[sea_orm(nested)]
+    pub profile: profile::Model,
+    pub rank: f32,
+    pub display_name_highlight: Option<String>,
+    pub bio_highlight: Option<String>,
 }
 
+/// Sort key of search results, used as the pagination cursor. The rank is
+/// carried as its bit pattern: an `f32` written out as a decimal can come back
+/// one ulp off, and the page boundary would then skip or repeat rows.
+pub type SearchCursor = (u32, Uuid);
+
+/// Matched terms are wrapped in `<mark>` tags. The text is HTML-escaped
+/// first (see `html_escaped`), so those tags are the only markup.
+const HIGHLIGHT_OPTIONS: &str = "StartSel=<mark>, StopSel=</mark>, MaxWords=35, MinWords=15";
+
+/// SQL for `column` with the characters HTML treats specially replaced by
+/// entities. Postgres' text search parser reads entities as single tokens,
+/// so highlighting never splits one.
+fn html_escaped(column: &str) -> String {
+    [
+        ("&", "&amp;"),
+        ("<", "&lt;"),
+        (">", "&gt;"),
+        ("\"", "&quot;"),
+        ("''", "&#39;"),
+    ]
+    .iter()
+    .fold(column.to_string(), |sql, (from, to)| {
+        format!("replace({sql}, '{from}', '{to}')")
+    })
+}
+
+/// Ranks profiles whose display name or bio match `query` (web search syntax:
+/// quoted phrases, `or`, `-excluded`), best match first, starting strictly
+/// after `after`.
+#[tracing::instrument(skip(db, query, after))]
+pub async fn search_profiles(
+    db: &impl ConnectionTrait,
+    query: &str,
+    after: Option<SearchCursor>,
+    limit: u64,
+) -> Result<Vec<ProfileSearchHit>, AppError> {
+    let _timer = QueryTimer::start("search_profiles");
+    let mut values: Vec<Value> = vec![query.into()];
+    let mut after_clause = "";
+
+    if let Some((rank, id)) = after {
+        values.push(f32::from_bits(rank).into());
+        values.push(id.into());
+        // `ts_rank` returns `real`; comparing as `real` keeps the match exact.
+        after_clause = "AND (ts_rank(p.search_vector, q) < $2::real \
+                        OR (ts_rank(p.search_vector, q) = $2::real AND p.id > $3))";
+    }
+    values.push((limit as i64).into());
+    let limit_param = values.len();
+
+    // The profile's own columns only; `search_vector` stays in the database.
+    let columns = profile::Column::iter()
+        .map(|column| format!("p.{}", column.as_str()))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let display_name = html_escaped("hit.display_name");
+    let bio = html_escaped("hit.bio");
+
+    // Rank and limit first, so `ts_headline` only runs on the returned page.
+    let sql = format!(
+        "SELECT hit.*, \
+                ts_headline('simple', {display_name}, q, '{HIGHLIGHT_OPTIONS}') AS display_name_highlight, \
+                ts_headline('simple', {bio}, q, '{HIGHLIGHT_OPTIONS}') AS bio_highlight \
+         FROM ( \
+             SELECT {columns}, ts_rank(p.search_vector, q) AS rank \
+             FROM profiles p, websearch_to_tsquery('simple', $1) q \
+             WHERE p.search_vector @@ q AND p.deleted_at IS NULL {after_clause} \
+             ORDER BY rank DESC, p.id ASC \
+             LIMIT ${limit_param} \
+         ) hit, websearch_to_tsquery('simple', $1) q \
+         ORDER BY hit.rank DESC, hit.id ASC"
+    );
+
+    Ok(
+        ProfileSearchHit::find_by_statement(Statement::from_sql_and_values(
+            DbBackend::Postgres,
+            sql,
+            values,
+        ))
+        .all(db)
+        .await?,
+    )
+}
+
+/// Emails are compared case-insensitively. If several profiles share an email
+/// the oldest one is returned.
+#[tracing::instrument(skip_all)]
+pub async fn find_by_email(
+    db: &impl ConnectionTrait,
+    email: &str,
+) -> Result<Option<profile::Model>, AppError> {
+    let _timer = QueryTimer::start("find_by_email");
+    Ok(active_profiles()
+        .filter(Expr::expr(Func::lower(Expr::col(profile::Column::Email))).eq(email.to_lowercase()))
+        .order_by_asc(profile::Column::CreatedAt)
+        .one(db)
+        .await?)
+}
+
+/// Optional filters for `list_profiles`. Matching is case-insensitive.
+#[derive(Default)]
+pub struct ProfileFilter {
+    pub display_name_prefix: Option<String>,
+    pub email_domain: Option<String>,
+}
+
+/// Sort key of the profile listing, used as the pagination cursor.
+pub type ProfileCursor = (DateTimeWithTimeZone, Uuid);
+
+/// Returns up to `limit` profiles ordered by `(created_at, id)`, starting
+/// strictly after `after` in the requested direction.
+#[tracing::instrument(skip(db, filter, after))]
+pub async fn list_profiles(
+    db: &impl ConnectionTrait,
+    filter: &ProfileFilter,
+    after: Option<ProfileCursor>,
+    order: SortOrder,
+    limit: u64,
+) -> Result<Vec<profile::Model>, AppError> {
+    let _timer = QueryTimer::start("list_profiles");
+    let mut query = active_profiles();
+
+    if let Some(prefix) = &filter.display_name_prefix {
+        query = query.filter(
+            Expr::expr(Func::lower(Expr::col(profile::Column::DisplayName))).like(
+                LikeExpr::new(format!("{}%", escape_like(&prefix.to_lowercase()))).escape('\\'),
+            ),
+        );
+    }
+    if let Some(domain) = &filter.email_domain {
+        query = query.filter(
+            Expr::expr(Func::lower(Expr::col(profile::Column::Email))).like(
+                LikeExpr::new(format!("%@{}", escape_like(&domain.to_lowercase()))).escape('\\'),
+            ),
+        );
+    }
+
+    if let Some((created_at, id)) = after {
+        let (newer, higher_id) = match order {
+            SortOrder::Asc => (
+                profile::Column::CreatedAt.gt(created_at),
+                profile::Column::Id.gt(id),
+            ),
+            SortOrder::Desc => (
+                profile::Column::CreatedAt.lt(created_at),
+                profile::Column::Id.lt(id),
+            ),
+        };
+        query = query.filter(
+            Condition::any().add(newer).add(
+                Condition::all()
+                    .add(profile::Column::CreatedAt.eq(created_at))
+                    .add(higher_id),
+            ),
+        );
+    }
+
+    let direction = match order {
+        SortOrder::Asc => Order::Asc,
+        SortOrder::Desc => Order::Desc,
+    };
+
+    Ok(query
+        .order_by(profile::Column::CreatedAt, direction.clone())
+        .order_by(profile::Column::Id, direction)
+        .limit(limit)
+        .all(db)
+        .await?)
+}
+
+/// Escapes `LIKE` wildcards so user input only ever matches literally.
+fn escape_like(input: &str) -> String {
+    input
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+/// Creates the profile for `auth_id`, or returns the active one that already
+/// exists, in a single `INSERT ... ON CONFLICT` statement, so concurrent
+/// sign-ins of a new user both get the same row instead of one failing on
+/// the unique constraint. A soft-deleted profile holding `auth_id` is left
+/// alone and gives `AppError::ProfileNotFound`; `restore_profile` deals with
+/// those.
+#[tracing::instrument(skip(db, email))]
 pub async fn create_profile(
-    db: &DatabaseConnection,
+    db: &impl ConnectionTrait,
     auth_id: String,
     email: String,
 ) -> Result<profile::Model, AppError> {
+    let _timer = QueryTimer::start("create_profile");
     let now = chrono::Utc::now().fixed_offset();
 
     let new_profile = profile::ActiveModel {
@@ -37,44 +271,362 @@ pub async fn create_profile(
         bio: Set(None),
         created_at: Set(now),
         updated_at: Set(now),
+        deleted_at: Set(None),
+        deleted_by: Set(None),
     };
 
-    Ok(new_profile.insert(db).await?)
+    // The no-op update makes Postgres return the existing row on conflict,
+    // which `DO NOTHING` would not.
+    let upsert = profile::Entity::insert(new_profile)
+        .on_conflict(
+            OnConflict::column(profile::Column::AuthId)
+                .update_column(profile::Column::AuthId)
+                .action_and_where(
+                    Expr::col((profile::Entity, profile::Column::DeletedAt)).is_null(),
+                )
+                .to_owned(),
+        )
+        .exec_with_returning(db)
+        .await;
+
+    // When the row on conflict is soft-deleted, nothing is updated and so
+    // nothing is returned, which SeaORM reports as `RecordNotFound`.
+    match upsert {
+        Err(DbErr::RecordNotFound(_)) => Err(AppError::ProfileNotFound),
+        result => Ok(result?),
+    }
 }
 
+/// Field changes for `update_profile`: `None` leaves a field untouched,
+/// `Some(None)` clears it and `Some(Some(v))` sets it.
+#[derive(Default)]
+pub struct ProfileChanges {
+    pub display_name: Option<Option<String>>,
+    pub bio: Option<Option<String>>,
+    pub avatar_url: Option<Option<String>>,
+}
+
+/// Applies `changes`. When `expected_version` is set the write only happens if
+/// the stored `updated_at` still equals it, otherwise the update fails with
+/// `AppError::PreconditionFailed` (optimistic concurrency). That includes the
+/// profile having been deleted since the client read it.
+#[tracing::instrument(skip(db, changes, expected_version))]
 pub async fn update_profile(
-    db: &DatabaseConnection,
+    db: &impl ConnectionTrait,
     auth_id: &str,
-    display_name: Option<String>,
-    bio: Option<String>,
-    avatar_url: Option<String>,
+    changes: ProfileChanges,
+    expected_version: Option<DateTimeWithTimeZone>,
 ) -> Result<profile::Model, AppError> {
-    let profile = find_by_auth_id(db, auth_id)
-        .await?
-        .ok_or_else(|| AppError::NotFound("profile not found".into()))?;
+    let _timer = QueryTimer::start("update_profile");
+    let profile = find_by_auth_id(db, auth_id).await?.ok_or_else(|| {
+        if expected_version.is_some() {
+            AppError::PreconditionFailed("resource does not exist".into())
+        } else {
+            AppError::ProfileNotFound
+        }
+    })?;
 
+    let version = expected_version.unwrap_or(profile.updated_at);
     let mut active: profile::ActiveModel = profile.into();
 
-    if let Some(name) = display_name {
-        active.display_name = Set(Some(name));
+    if let Some(name) = changes.display_name {
+        active.display_name = Set(name);
     }
-    if let Some(text) = bio {
-        active.bio = Set(Some(text));
+    if let Some(text) = changes.bio {
+        active.bio = Set(text);
     }
-    if let Some(url) = avatar_url {
-        active.avatar_url = Set(Some(url));
+    if let Some(url) = changes.avatar_url {
+        active.avatar_url = Set(url);
     }
     active.updated_at = Set(chrono::Utc::now().fixed_offset());
 
-    Ok(active.update(db).await?)
+    // Without an expected version, a concurrent write is simply overwritten.
+    let mut update = profile::Entity::update(active);
+    if expected_version.is_some() {
+        update = update.filter(profile::Column::UpdatedAt.eq(version));
+    }
+
+    match update.exec(db).await {
+        Err(DbErr::RecordNotUpdated) if expected_version.is_some() => Err(
+            AppError::PreconditionFailed("resource has been modified".into()),
+        ),
+        result => Ok(result?),
+    }
 }
 
-pub async fn delete_profile(db: &DatabaseConnection, auth_id: &str) -> Result<(), AppError> {
+/// Soft-deletes the profile on behalf of `deleted_by`, the owner's or an
+/// operator's `auth_id`. The purge job removes it for good once the
+/// retention window passes; until then `restore_profile` can bring back a
+/// profile its owner deleted.
+#[tracing::instrument(skip(db))]
+pub async fn delete_profile(
+    db: &impl ConnectionTrait,
+    auth_id: &str,
+    deleted_by: &str,
+) -> Result<(), AppError> {
+    let _timer = QueryTimer::start("delete_profile");
     let profile = find_by_auth_id(db, auth_id)
         .await?
-        .ok_or_else(|| AppError::NotFound("profile not found".into()))?;
+        .ok_or(AppError::ProfileNotFound)?;
 
-    profile::Entity::delete_by_id(profile.id).exec(db).await?;
+    let now = chrono::Utc::now().fixed_offset();
+    let mut active: profile::ActiveModel = profile.into();
+    active.deleted_at = Set(Some(now));
+    active.deleted_by = Set(Some(deleted_by.to_string()));
+    active.updated_at = Set(now);
+    active.update(db).await?;
 
     Ok(())
 }
+
+/// Outcome of `restore_profile`.
+pub enum Restore {
+    /// The profile was deleted within the retention window and is active
+    /// again.
+    Restored(profile::Model),
+    /// The profile was past its retention window and has been deleted. Its
+    /// avatars are left for the caller to remove once that is committed.
+    Purged(Uuid),
+    /// The profile was deleted by someone other than its owner, an
+    /// operator, and stays deleted until it is purged.
+    DeletedByOperator,
+    /// There is no soft-deleted profile to restore.
+    NotDeleted,
+}
+
+/// Brings back a profile its owner soft-deleted less than `retention` ago.
+/// Deletions made by anyone else are not undone. A deleted profile past its
+/// retention window that the purge job has not reached yet is removed
+/// immediately, so a fresh one can take its `auth_id`.
+#[tracing::instrument(skip(db, retention))]
+pub async fn restore_profile(
+    db: &impl ConnectionTrait,
+    auth_id: &str,
+    retention: chrono::Duration,
+) -> Result<Restore, AppError> {
+    let _timer = QueryTimer::start("restore_profile");
+    let Some(profile) = profile::Entity::find()
+        .filter(profile::Column::AuthId.eq(auth_id))
+        .filter(profile::Column::DeletedAt.is_not_null())
+        .one(db)
+        .await?
+    else {
+        return Ok(Restore::NotDeleted);
+    };
+
+    let now = chrono::Utc::now().fixed_offset();
+    if profile.deleted_at.is_some_and(|at| at + retention <= now) {
+        profile::Entity::delete_by_id(profile.id).exec(db).await?;
+        return Ok(Restore::Purged(profile.id));
+    }
+    if profile.deleted_by.as_deref() != Some(auth_id) {
+        return Ok(Restore::DeletedByOperator);
+    }
+
+    let mut active: profile::ActiveModel = profile.into();
+    active.deleted_at = Set(None);
+    active.deleted_by = Set(None);
+    active.updated_at = Set(now);
+
+    Ok(Restore::Restored(active.update(db).await?))
+}
+
+/// Permanently removes profiles soft-deleted before `cutoff`, together with
+/// their uploaded avatars. Returns the number of profiles removed.
+#[tracing::instrument(skip(db, storage))]
+pub async fn purge_deleted_profiles(
+    db: &impl ConnectionTrait,
+    storage: &dyn Storage,
+    cutoff: DateTimeWithTimeZone,
+) -> Result<usize, AppError> {
+    let _timer = QueryTimer::start("purge_deleted_profiles");
+    let purged = profile::Entity::delete_many()
+        .filter(profile::Column::DeletedAt.lt(cutoff))
+        .exec_with_returning(db)
+        .await?;
+
+    for profile in &purged {
+        avatar::remove_all(storage, profile.id).await;
+    }
+
+    Ok(purged.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use sea_orm::TransactionTrait;
+
+    use super::*;
+    use crate::db;
+
+    /// A transaction on the test database, rolled back when dropped.
+    async fn test_txn() -> DatabaseTransaction {
+        db::test_database().await.begin().await.unwrap()
+    }
+
+    fn new_auth_id() -> String {
+        format!("test-{}", Uuid::new_v4())
+    }
+
+    /// Creates a profile for `auth_id` that its owner deleted `days` ago.
+    async fn deleted_days_ago(txn: &DatabaseTransaction, auth_id: &str, days: i64) -> Uuid {
+        deleted_by_days_ago(txn, auth_id, auth_id, days).await
+    }
+
+    async fn deleted_by_days_ago(
+        txn: &DatabaseTransaction,
+        auth_id: &str,
+        deleted_by: &str,
+        days: i64,
+    ) -> Uuid {
+        let profile = create_profile(txn, auth_id.into(), "user@example.com".into())
+            .await
+            .unwrap();
+        delete_profile(txn, auth_id, deleted_by).await.unwrap();
+
+        let mut active: profile::ActiveModel = profile.into();
+        active.deleted_at = Set(Some(
+            chrono::Utc::now().fixed_offset() - chrono::Duration::days(days),
+        ));
+        active.update(txn).await.unwrap().id
+    }
+
+    #[tokio::test]
+    async fn create_profile_returns_the_existing_profile() {
+        let txn = test_txn().await;
+        let auth_id = new_auth_id();
+
+        let created = create_profile(&txn, auth_id.clone(), "a@example.com".into())
+            .await
+            .unwrap();
+        let again = create_profile(&txn, auth_id, "b@example.com".into())
+            .await
+            .unwrap();
+
+        assert_eq!(again.id, created.id);
+        assert_eq!(again.email, "a@example.com");
+    }
+
+    #[tokio::test]
+    async fn create_profile_leaves_a_soft_deleted_profile_alone() {
+        let txn = test_txn().await;
+        let auth_id = new_auth_id();
+        let deleted = deleted_days_ago(&txn, &auth_id, 0).await;
+
+        let result = create_profile(&txn, auth_id.clone(), "user@example.com".into()).await;
+
+        assert!(
+            matches!(result, Err(AppError::ProfileNotFound)),
+            "{result:?}"
+        );
+        let stored = profile::Entity::find_by_id(deleted)
+            .one(&txn)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(stored.deleted_at.is_some());
+    }
+
+    #[tokio::test]
+    async fn restore_profile_within_retention() {
+        let txn = test_txn().await;
+        let auth_id = new_auth_id();
+        let deleted = deleted_days_ago(&txn, &auth_id, 3).await;
+
+        match restore_profile(&txn, &auth_id, chrono::Duration::days(30)).await {
+            Ok(Restore::Restored(profile)) => {
+                assert_eq!(profile.id, deleted);
+                assert!(profile.deleted_at.is_none());
+                assert!(profile.deleted_by.is_none());
+            }
+            _ => panic!("expected the profile to be restored"),
+        }
+        assert!(matches!(
+            restore_profile(&txn, &auth_id, chrono::Duration::days(30)).await,
+            Ok(Restore::NotDeleted)
+        ));
+    }
+
+    #[tokio::test]
+    async fn restore_profile_purges_an_expired_profile() {
+        let txn = test_txn().await;
+        let auth_id = new_auth_id();
+        let deleted = deleted_days_ago(&txn, &auth_id, 31).await;
+
+        match restore_profile(&txn, &auth_id, chrono::Duration::days(30)).await {
+            Ok(Restore::Purged(id)) => assert_eq!(id, deleted),
+            _ => panic!("expected the profile to be purged"),
+        }
+        let stored = profile::Entity::find_by_id(deleted)
+            .one(&txn)
+            .await
+            .unwrap();
+        assert!(stored.is_none());
+
+        let created = create_profile(&txn, auth_id, "user@example.com".into())
+            .await
+            .unwrap();
+        assert_ne!(created.id, deleted);
+    }
+
+    #[tokio::test]
+    async fn restore_profile_leaves_an_operator_deletion_alone() {
+        let txn = test_txn().await;
+        let auth_id = new_auth_id();
+        let deleted = deleted_by_days_ago(&txn, &auth_id, "admin-1", 3).await;
+
+        assert!(matches!(
+            restore_profile(&txn, &auth_id, chrono::Duration::days(30)).await,
+            Ok(Restore::DeletedByOperator)
+        ));
+        assert!(find_by_auth_id(&txn, &auth_id).await.unwrap().is_none());
+        let stored = profile::Entity::find_by_id(deleted)
+            .one(&txn)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored.deleted_by.as_deref(), Some("admin-1"));
+    }
+
+    #[tokio::test]
+    async fn restore_profile_purges_an_expired_operator_deletion() {
+        let txn = test_txn().await;
+        let auth_id = new_auth_id();
+        let deleted = deleted_by_days_ago(&txn, &auth_id, "admin-1", 31).await;
+
+        match restore_profile(&txn, &auth_id, chrono::Duration::days(30)).await {
+            Ok(Restore::Purged(id)) => assert_eq!(id, deleted),
+            _ => panic!("expected the profile to be purged"),
+        }
+    }
+
+    #[tokio::test]
+    async fn search_highlights_escape_html() {
+        let txn = test_txn().await;
+        let auth_id = new_auth_id();
+        let created = create_profile(&txn, auth_id.clone(), "user@example.com".into())
+            .await
+            .unwrap();
+        let name = "<img src=x onerror='alert(1)'> Zyxwvut & \"Co\"".to_string();
+        let changes = ProfileChanges {
+            display_name: Some(Some(name.clone())),
+            ..Default::default()
+        };
+        update_profile(&txn, &auth_id, changes, None).await.unwrap();
+
+        let hits = search_profiles(&txn, "zyxwvut", None, 10).await.unwrap();
+        let hit = hits
+            .iter()
+            .find(|hit| hit.profile.id == created.id)
+            .unwrap();
+
+        assert_eq!(hit.profile.display_name.as_deref(), Some(name.as_str()));
+        assert_eq!(
+            hit.display_name_highlight.as_deref(),
+            Some(
+                "&lt;img src=x onerror=&#39;alert(1)&#39;&gt; <mark>Zyxwvut</mark> \
+                 &amp; &quot;Co&quot;"
+            )
+        );
+    }
+}
@@ -0,0 +1,64 @@
+use std::time::Duration;
+
+use tokio_util::sync::CancellationToken;
+
+use crate::services::health::Draining;
+
+/// Spawns the task that starts shutdown on SIGINT or SIGTERM. Readiness
+/// flips to `draining` at once; the returned token is cancelled `delay`
+/// later, which closes the listeners and stops the background jobs.
+pub fn on_signal(draining: Draining, delay: Duration) -> CancellationToken {
+    let token = CancellationToken::new();
+    let shutdown = token.clone();
+
+    tokio::spawn(async move {
+        signal().await;
+        tracing::info!("Shutdown signal received, draining");
+        draining.start();
+        tokio::time::sleep(delay).await;
+        shutdown.cancel();
+    });
+
+    token
+}
+
+/// Drives `work` to completion, but gives up `timeout` after `shutdown` is
+/// cancelled. Returns false if the timeout cut it short.
+pub async fn drain(
+    work: impl Future<Output = ()>,
+    shutdown: &CancellationToken,
+    timeout: Duration,
+) -> bool {
+    let deadline = async {
+        shutdown.cancelled().await;
+        tokio::time::sleep(timeout).await;
+    };
+
+    tokio::select! {
+        () = work => true,
+        () = deadline => false,
+    }
+}
+
+async fn signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("Failed to listen for SIGINT");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("Failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        () = ctrl_c => {}
+        () = terminate => {}
+    }
+}
@@ -0,0 +1,290 @@
+use std::borrow::Cow;
+use std::io::{self, Write};
+use std::sync::LazyLock;
+use std::time::Duration;
+
+use axum::extract::{MatchedPath, Request};
+use axum::middleware::Next;
+use axum::response::Response;
+use opentelemetry::global;
+use opentelemetry::trace::{TraceContextExt, TracerProvider};
+use opentelemetry_http::HeaderExtractor;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::Resource;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{Sampler, SdkTracerProvider};
+use regex::Regex;
+use tower_http::request_id::RequestId;
+use tracing::Span;
+use tracing::field::Empty;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer};
+
+use crate::config::{Config, LogFormat};
+
+const X_REQUEST_ID: &str = "x-request-id";
+
+/// Installs the global subscriber in the configured format. Every line goes
+/// through `redact` before it reaches stdout, whichever layer produced it.
+///
+/// When an OTLP endpoint is configured, spans are also exported there; the
+/// returned provider must be shut down on exit to flush the last batch.
+pub fn init(config: &Config) -> Option<SdkTracerProvider> {
+    let fmt = tracing_subscriber::fmt::layer().with_writer(|| RedactingStdout);
+    let fmt = match config.logging.format {
+        LogFormat::Pretty => fmt.pretty().boxed(),
+        LogFormat::Compact => fmt.compact().boxed(),
+        LogFormat::Json => fmt
+            .json()
+            .flatten_event(true)
+            .with_current_span(true)
+            .with_span_list(false)
+            .boxed(),
+    };
+
+    let provider = config.tracing.otlp_endpoint.as_deref().map(|endpoint| {
+        let exporter = SpanExporter::builder()
+            .with_http()
+            .with_endpoint(format!("{}/v1/traces", endpoint.trim_end_matches('/')))
+            .build()
+            .expect("Failed to build OTLP span exporter");
+
+        SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+                config.tracing.sampling_ratio,
+            ))))
+            .with_resource(
+                Resource::builder()
+                    .with_service_name(config.tracing.service_name.clone())
+                    .build(),
+            )
+            .build()
+    });
+
+    let otel = provider.as_ref().map(|provider| {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        tracing_opentelemetry::layer().with_tracer(provider.tracer(env!("CARGO_PKG_NAME")))
+    });
+
+    tracing_subscriber::registry()
+        .with(EnvFilter::new(&config.logging.filter))
+        .with(fmt)
+        .with(otel)
+        .init();
+
+    provider
+}
+
+/// Root span of a request. `route`, `user_id`, `status` and `latency_ms` are
+/// filled in as they become known. Only the path is logged: query strings can
+/// carry emails and tokens.
+///
+/// A W3C `traceparent` header makes the span a child of the caller's trace.
+/// Without trace export the propagator is a no-op and the header is ignored.
+pub fn make_request_span(req: &Request) -> Span {
+    let request_id = req
+        .extensions()
+        .get::<RequestId>()
+        .and_then(|id| id.header_value().to_str().ok())
+        .unwrap_or_default();
+
+    let span = tracing::info_span!(
+        "request",
+        method = %req.method(),
+        path = %req.uri().path(),
+        route = Empty,
+        request_id = %request_id,
+        user_id = Empty,
+        status = Empty,
+        latency_ms = Empty,
+        otel.name = %req.method(),
+        otel.kind = "server",
+        otel.status_code = Empty,
+    );
+
+    let parent = global::get_text_map_propagator(|propagator| {
+        propagator.extract(&HeaderExtractor(req.headers()))
+    });
+    // Only fails for a span that has already started, which this one has not.
+    let _ = span.set_parent(parent);
+
+    span
+}
+
+pub fn on_response(res: &Response, latency: Duration, span: &Span) {
+    span.record("status", res.status().as_u16());
+    span.record("latency_ms", latency.as_millis() as u64);
+    if res.status().is_server_error() {
+        span.record("otel.status_code", "error");
+    }
+    tracing::info!("request completed");
+}
+
+/// Records the matched route template (e.g. `/users/{id}`) on the request
+/// span, and names the exported span after it (`GET /users/{id}`). Added with
+/// `Router::layer`, so it runs after routing.
+pub async fn record_route(req: Request, next: Next) -> Response {
+    if let Some(route) = req.extensions().get::<MatchedPath>() {
+        let span = Span::current();
+        span.record("route", route.as_str());
+        // The exported span has already started, so `otel.name` can no
+        // longer be recorded; rename it directly instead.
+        span.context()
+            .span()
+            .update_name(format!("{} {}", req.method(), route.as_str()));
+    }
+    next.run(req).await
+}
+
+/// Longest client-supplied `X-Request-Id` that is kept; generated ids are
+/// 36-character UUIDs.
+const MAX_REQUEST_ID_LEN: usize = 64;
+
+/// Drops a client's `X-Request-Id` unless it is a single short value made of
+/// ASCII letters, digits and `-_.:`, so `SetRequestIdLayer` generates a new
+/// id instead. The id ends up in logs, response headers and error bodies, so
+/// arbitrary client text must not.
+pub async fn check_request_id(mut req: Request, next: Next) -> Response {
+    let mut ids = req.headers().get_all(X_REQUEST_ID).iter();
+    let valid = match (ids.next(), ids.next()) {
+        (Some(id), None) => is_valid_request_id(id.as_bytes()),
+        (None, _) => true,
+        (Some(_), Some(_)) => false,
+    };
+    if !valid {
+        req.headers_mut().remove(X_REQUEST_ID);
+    }
+    next.run(req).await
+}
+
+fn is_valid_request_id(id: &[u8]) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_REQUEST_ID_LEN
+        && id
+            .iter()
+            .all(|b| b.is_ascii_alphanumeric() || b"-_.:".contains(b))
+}
+
+static REDACTIONS: LazyLock<[(Regex, &str); 3]> = LazyLock::new(|| {
+    [
+        // Credentials after an auth scheme, e.g. a logged Authorization header.
+        (
+            Regex::new(r"(?i)\b(bearer|basic)\s+[A-Za-z0-9._~+/=-]+").unwrap(),
+            "$1 [REDACTED]",
+        ),
+        // JWTs anywhere else; their header always starts with `eyJ`.
+        (
+            Regex::new(r"\beyJ[A-Za-z0-9_-]*\.[A-Za-z0-9_-]+\.[A-Za-z0-9_-]*").unwrap(),
+            "[REDACTED_JWT]",
+        ),
+        // Email addresses, including URL-encoded ones.
+        (
+            Regex::new(r"[A-Za-z0-9._%+-]+(@|%40)[A-Za-z0-9.-]+\.[A-Za-z]{2,}").unwrap(),
+            "[REDACTED_EMAIL]",
+        ),
+    ]
+});
+
+/// Masks bearer credentials, JWTs and email addresses in `text`.
+pub fn redact(text: &str) -> Cow<'_, str> {
+    let mut redacted = Cow::Borrowed(text);
+    for (pattern, replacement) in REDACTIONS.iter() {
+        if let Cow::Owned(replaced) = pattern.replace_all(&redacted, *replacement) {
+            redacted = Cow::Owned(replaced);
+        }
+    }
+    redacted
+}
+
+/// Stdout writer that redacts each formatted event. The fmt layer writes an
+/// event in a single `write_all`, so a value is never split across calls.
+struct RedactingStdout;
+
+impl Write for RedactingStdout {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let text = String::from_utf8_lossy(buf);
+        io::stdout().write_all(redact(&text).as_bytes())?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stdout().flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::Router;
+    use axum::body::Body;
+    use axum::extract::Extension;
+    use axum::middleware;
+    use axum::routing::get;
+    use tower::ServiceExt;
+    use tower_http::request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer};
+
+    use super::*;
+
+    /// The request id the handler saw and the one echoed back, for a request
+    /// sending `ids` as `X-Request-Id`.
+    async fn request_id(ids: &[&str]) -> (String, String) {
+        let app = Router::new()
+            .route(
+                "/",
+                get(|Extension(id): Extension<RequestId>| async move {
+                    id.header_value().to_str().unwrap().to_string()
+                }),
+            )
+            .layer(PropagateRequestIdLayer::x_request_id())
+            .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
+            .layer(middleware::from_fn(check_request_id));
+
+        let mut req = Request::builder().uri("/");
+        for id in ids {
+            req = req.header(X_REQUEST_ID, *id);
+        }
+        let res = app.oneshot(req.body(Body::empty()).unwrap()).await.unwrap();
+
+        let echoed = res.headers()[X_REQUEST_ID].to_str().unwrap().to_string();
+        let body = axum::body::to_bytes(res.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (String::from_utf8(body.to_vec()).unwrap(), echoed)
+    }
+
+    #[tokio::test]
+    async fn keeps_well_formed_client_ids() {
+        for id in ["abc-123", "0af7651916cd43dd8448eb211c80319c", "svc:req_1.2"] {
+            assert_eq!(request_id(&[id]).await, (id.to_string(), id.to_string()));
+        }
+    }
+
+    #[tokio::test]
+    async fn replaces_malformed_client_ids() {
+        let too_long = "a".repeat(MAX_REQUEST_ID_LEN + 1);
+        for ids in [
+            &[""][..],
+            &["has space"],
+            &["line\tbreak"],
+            &["<script>"],
+            &[too_long.as_str()],
+            &["one", "two"],
+        ] {
+            let (seen, echoed) = request_id(ids).await;
+            assert_eq!(seen, echoed);
+            assert!(
+                uuid::Uuid::parse_str(&seen).is_ok(),
+                "{ids:?} kept as {seen}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn generates_an_id_when_none_is_sent() {
+        let (seen, echoed) = request_id(&[]).await;
+        assert_eq!(seen, echoed);
+        assert!(uuid::Uuid::parse_str(&seen).is_ok());
+    }
+}